@@ -10,28 +10,170 @@ fn main() {
 mod download {
     use bitcoin_hashes::{sha256, Hash};
     use flate2::read::GzDecoder;
-    use std::fs::File;
-    use std::io::{BufRead, BufReader, Cursor};
     use std::os::unix::fs::PermissionsExt;
-    use std::path::Path;
-    use std::str::FromStr;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
     use tar::Archive;
     include!("src/versions.rs");
+    include!("src/checksum.rs");
 
     const GITHUB_URL: &str = "https://github.com/chaintope/esplora-tapyrus/releases/download";
 
-    fn get_expected_sha256(filename: &str) -> Result<sha256::Hash, ()> {
-        let file = File::open("sha256").map_err(|_| ())?;
-        for line in BufReader::new(file).lines().flatten() {
-            let tokens: Vec<_> = line.split("  ").collect();
-            if tokens.len() == 2 && filename == tokens[1] {
-                return sha256::Hash::from_str(tokens[0]).map_err(|_| ());
+    /// Maximum number of download attempts
+    const DOWNLOAD_ATTEMPTS: u32 = 4;
+
+    /// Timeout of every download attempt
+    const DOWNLOAD_TIMEOUT_SECS: u64 = 300;
+
+    /// Directory where the electrs executables are cached across builds: `ELECTRSD_CACHE_DIR` if
+    /// set, the XDG cache directory otherwise, falling back to `OUT_DIR`
+    fn cache_dir() -> PathBuf {
+        if let Some(dir) = std::env::var_os("ELECTRSD_CACHE_DIR") {
+            return PathBuf::from(dir);
+        }
+        if let Some(dir) = std::env::var_os("XDG_CACHE_HOME") {
+            return Path::new(&dir).join("electrsd");
+        }
+        if let Some(home) = std::env::var_os("HOME") {
+            return Path::new(&home).join(".cache").join("electrsd");
+        }
+        Path::new(&std::env::var_os("OUT_DIR").unwrap()).join("electrs")
+    }
+
+    fn invalid_archive(filename: &str, e: std::io::Error) -> ! {
+        panic!("invalid archive {}: {}", filename, e)
+    }
+
+    /// Fail the build if the sha256 of `bytes` is not the one listed for `filename` in `table`
+    fn verify(table: &str, filename: &str, bytes: &[u8]) {
+        let hash = sha256::Hash::hash(bytes).to_string();
+        if let Err(e) = verify_sha256(table, filename, &hash) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Return the bytes of the archive `filename`, read from `ELECTRSD_ARCHIVE` if set, from the
+    /// local directory of a `file://` `ELECTRSD_DOWNLOAD_ENDPOINT` or downloaded otherwise
+    fn fetch(filename: &str) -> Vec<u8> {
+        if let Some(archive) = std::env::var_os("ELECTRSD_ARCHIVE") {
+            return std::fs::read(&archive)
+                .unwrap_or_else(|e| panic!("cannot read ELECTRSD_ARCHIVE {:?}: {}", archive, e));
+        }
+
+        let download_endpoint =
+            std::env::var("ELECTRSD_DOWNLOAD_ENDPOINT").unwrap_or(GITHUB_URL.to_string());
+        let url = format!("{}/{}/{}", download_endpoint, VERSION, filename);
+        if let Some(path) = url.strip_prefix("file://") {
+            return std::fs::read(path)
+                .unwrap_or_else(|e| panic!("cannot read archive {}: {}", path, e));
+        }
+
+        get_with_retries(&url)
+    }
+
+    /// Download `url`, retrying with exponential backoff on network errors and server failures
+    fn get_with_retries(url: &str) -> Vec<u8> {
+        let mut delay = Duration::from_secs(1);
+        let mut attempt = 1;
+        loop {
+            match get(url) {
+                Ok(bytes) => return bytes,
+                Err(Failure::Permanent(e)) => panic!("cannot download {}: {}", url, e),
+                Err(Failure::Transient(e)) if attempt >= DOWNLOAD_ATTEMPTS => {
+                    panic!("cannot download {} after {} attempts: {}", url, attempt, e)
+                }
+                Err(Failure::Transient(e)) => {
+                    println!(
+                        "cargo:warning=download of {} failed (attempt {}/{}): {}, retrying in {:?}",
+                        url, attempt, DOWNLOAD_ATTEMPTS, e, delay
+                    );
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
             }
         }
-        Err(())
+    }
+
+    enum Failure {
+        /// Worth retrying, like a network error or a server failure
+        Transient(String),
+        /// Retrying would fail the same way, like a missing archive
+        Permanent(String),
+    }
+
+    fn get(url: &str) -> Result<Vec<u8>, Failure> {
+        let mut request = minreq::get(url).with_timeout(DOWNLOAD_TIMEOUT_SECS);
+        if let Some(proxy) = proxy_for(url) {
+            let parsed = minreq::Proxy::new(proxy.as_str())
+                .map_err(|e| Failure::Permanent(format!("invalid proxy {}: {}", proxy, e)))?;
+            request = request.with_proxy(parsed);
+        }
+        let response = request
+            .send()
+            .map_err(|e| Failure::Transient(e.to_string()))?;
+        let message = format!(
+            "http status {} {}",
+            response.status_code, response.reason_phrase
+        );
+        match response.status_code {
+            200..=299 => Ok(response.into_bytes()),
+            408 | 429 | 500..=599 => Err(Failure::Transient(message)),
+            _ => Err(Failure::Permanent(message)),
+        }
+    }
+
+    /// Proxy to use for `url` according to the `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY` and
+    /// `NO_PROXY` env vars, lowercase variants are accepted too
+    fn proxy_for(url: &str) -> Option<String> {
+        let var = |name: &str| {
+            std::env::var(name)
+                .or_else(|_| std::env::var(name.to_lowercase()))
+                .ok()
+                .filter(|v| !v.is_empty())
+        };
+        let host = url
+            .split("://")
+            .nth(1)
+            .and_then(|rest| rest.split(&['/', ':'][..]).next())
+            .unwrap_or_default();
+        if let Some(no_proxy) = var("NO_PROXY") {
+            let excluded = no_proxy.split(',').map(str::trim).any(|pattern| {
+                let pattern = pattern.trim_start_matches('.');
+                pattern == "*"
+                    || (!pattern.is_empty()
+                        && (host == pattern || host.ends_with(&format!(".{}", pattern))))
+            });
+            if excluded {
+                return None;
+            }
+        }
+        let scheme_var = if url.starts_with("https://") {
+            "HTTPS_PROXY"
+        } else {
+            "HTTP_PROXY"
+        };
+        var(scheme_var).or_else(|| var("ALL_PROXY"))
     }
 
     pub fn download() {
+        for var in &[
+            "ELECTRSD_SKIP_DOWNLOAD",
+            "ELECTRSD_DOWNLOAD_ENDPOINT",
+            "ELECTRSD_ARCHIVE",
+            "ELECTRSD_CACHE_DIR",
+            "XDG_CACHE_HOME",
+            "HTTPS_PROXY",
+            "HTTP_PROXY",
+            "ALL_PROXY",
+            "NO_PROXY",
+        ] {
+            println!("cargo:rerun-if-env-changed={}", var);
+        }
+        for file in &["build.rs", "sha256", "src/versions.rs", "src/checksum.rs"] {
+            println!("cargo:rerun-if-changed={}", file);
+        }
+
         if std::env::var_os("ELECTRSD_SKIP_DOWNLOAD").is_some() {
             return;
         }
@@ -39,45 +181,107 @@ mod download {
         if !HAS_FEATURE {
             return;
         }
-        let download_filename_without_extension = electrs_name();
+        let env = |name: &str| std::env::var(name).unwrap_or_default();
+        let (arch, os) = (env("CARGO_CFG_TARGET_ARCH"), env("CARGO_CFG_TARGET_OS"));
+        let supported = supported_targets(VERSION);
+        let target = target_triple(&arch, &os, &env("CARGO_CFG_TARGET_ENV"))
+            .filter(|target| supported.contains(&target.as_str()))
+            .unwrap_or_else(|| {
+                panic!(
+                    "electrs {} is not available for {} {}, supported platforms: {}. \
+                     Set ELECTRSD_SKIP_DOWNLOAD and provide electrs with ELECTRS_EXEC instead",
+                    VERSION,
+                    arch,
+                    os,
+                    supported.join(", ")
+                )
+            });
+        let download_filename_without_extension = electrs_name(&target);
         let download_filename = format!("{}.tar.gz", download_filename_without_extension);
-        dbg!(&download_filename);
-        // let expected_hash = get_expected_sha256(&download_filename).unwrap();
-        let out_dir = std::env::var_os("OUT_DIR").unwrap();
-        let electrs_exe_home = Path::new(&out_dir).join("electrs");
-        let destination_filename = electrs_exe_home
-            .join(&download_filename_without_extension)
-            .join("electrs");
-
-        dbg!(&destination_filename);
-        if !destination_filename.exists() {
-            println!("filename:{} version:{}", download_filename, VERSION);
 
-            let download_endpoint =
-                std::env::var("ELECTRSD_DOWNLOAD_ENDPOINT").unwrap_or(GITHUB_URL.to_string());
-            let url = format!("{}/{}/{}", download_endpoint, VERSION, download_filename);
+        let table = std::fs::read_to_string("sha256").expect("missing `sha256` file");
+        let hash = match expected_sha256(&table, &download_filename) {
+            Some(hash) => hash.to_lowercase(),
+            None => panic!(
+                "no sha256 listed for {} in the `sha256` file: download it from {}/{}/{}, check it \
+                 against the release and add the output of `sha256sum {}` to the `sha256` file",
+                download_filename, GITHUB_URL, VERSION, download_filename, download_filename
+            ),
+        };
+
+        // keyed by version and hash, so that a changed archive is never confused with a cached one
+        let cache_dir = cache_dir();
+        let destination_dir =
+            cache_dir.join(format!("{}-{}", download_filename_without_extension, hash));
+        let destination_filename = destination_dir.join("electrs");
+
+        if !destination_filename.exists() {
+            println!(
+                "cargo:warning=installing electrs {} from {} in {}",
+                VERSION,
+                download_filename,
+                destination_dir.display()
+            );
 
-            let downloaded_bytes = minreq::get(url).send().unwrap().into_bytes();
+            let downloaded_bytes = fetch(&download_filename);
+            verify(&table, &download_filename, &downloaded_bytes);
 
-            // let downloaded_hash = sha256::Hash::hash(&downloaded_bytes);
-            // assert_eq!(expected_hash, downloaded_hash);
+            // extract in a directory of this build only, then move it in place at once, so that
+            // parallel builds never see a partially extracted executable
+            let tmp_dir = cache_dir.join(format!(
+                ".{}-{}.tmp",
+                download_filename_without_extension,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&tmp_dir);
+            std::fs::create_dir_all(&tmp_dir)
+                .unwrap_or_else(|e| panic!("cannot create {:?}: {}", tmp_dir, e));
+            let tmp_filename = tmp_dir.join("electrs");
 
             let mut archive = Archive::new(GzDecoder::new(&downloaded_bytes[..]));
+            for entry in archive
+                .entries()
+                .unwrap_or_else(|e| invalid_archive(&download_filename, e))
+            {
+                let mut entry = entry.unwrap_or_else(|e| invalid_archive(&download_filename, e));
+                if entry
+                    .path()
+                    .unwrap_or_else(|e| invalid_archive(&download_filename, e))
+                    .ends_with("electrs")
+                {
+                    entry
+                        .unpack(&tmp_filename)
+                        .unwrap_or_else(|e| invalid_archive(&download_filename, e));
+                    std::fs::set_permissions(&tmp_filename, std::fs::Permissions::from_mode(0o755))
+                        .unwrap_or_else(|e| invalid_archive(&download_filename, e));
+                }
+            }
+            let is_executable = std::fs::metadata(&tmp_filename)
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false);
+            if !is_executable {
+                panic!(
+                    "archive {} does not contain an `electrs` executable",
+                    download_filename
+                );
+            }
 
-            std::fs::create_dir_all(destination_filename.parent().unwrap()).unwrap();
-            for mut entry in archive.entries().unwrap().flatten() {
-                if let Ok(file) = entry.path() {
-                    if file.ends_with("electrs") {
-                        entry.unpack(&destination_filename).unwrap();
-
-                        std::fs::set_permissions(
-                            &destination_filename,
-                            std::fs::Permissions::from_mode(0o755),
-                        )
-                        .unwrap();
-                    }
+            if let Err(e) = std::fs::rename(&tmp_dir, &destination_dir) {
+                // another build completed the extraction meanwhile
+                let _ = std::fs::remove_dir_all(&tmp_dir);
+                if !destination_filename.exists() {
+                    panic!(
+                        "cannot move electrs in the cache {:?}: {}",
+                        destination_dir, e
+                    );
                 }
             }
         }
+
+        println!(
+            "cargo:rustc-env=ELECTRSD_DOWNLOADED_EXE={}",
+            destination_filename.display()
+        );
+        println!("cargo:rerun-if-changed={}", destination_filename.display());
     }
 }
@@ -0,0 +1,634 @@
+//! Async variant of [crate::ElectrsD] based on tokio, enabled by the `async` feature
+//!
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use electrum_client::raw_client::{ElectrumPlaintextStream, RawClient};
+use electrum_client::tapyrus::{MalFixTxid, Script, Transaction};
+use electrum_client::{ElectrumApi, GetHistoryRes, HeaderNotification};
+use log::{error, warn};
+use regex::Regex;
+use tapyrusd::anyhow;
+use tapyrusd::anyhow::Context;
+use tapyrusd::TapyrusD;
+use tokio::process::{Child, Command};
+
+use crate::logs::{log_not_found, LogBuffer, ERROR_LOG_LINES};
+use crate::{ext, Backoff, Conf, DataDir, Error, Launch, Proxy};
+
+/// Electrum client usable from async code.
+///
+/// Calls are executed by a blocking [RawClient] on the tokio blocking thread pool, so that they
+/// never block the runtime.
+#[derive(Clone)]
+pub struct AsyncClient {
+    inner: Arc<RawClient<ElectrumPlaintextStream>>,
+}
+
+impl AsyncClient {
+    /// Connect to the given electrum server
+    pub async fn connect(addr: SocketAddr) -> Result<Self, Error> {
+        let client = spawn_blocking(move || RawClient::new(addr, None)).await?;
+        Ok(AsyncClient {
+            inner: Arc::new(client),
+        })
+    }
+
+    /// Run `f` with the underlying blocking client on the tokio blocking thread pool
+    pub async fn call<T, E, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&RawClient<ElectrumPlaintextStream>) -> Result<T, E> + Send + 'static,
+        E: Into<Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        spawn_blocking(move || f(&inner)).await
+    }
+
+    /// Ping the server
+    pub async fn ping(&self) -> Result<(), Error> {
+        self.call(|c| c.ping()).await
+    }
+
+    /// Subscribe to block headers, returning the current tip
+    pub async fn block_headers_subscribe(&self) -> Result<HeaderNotification, Error> {
+        self.call(|c| c.block_headers_subscribe()).await
+    }
+
+    /// Get the transaction with the given txid
+    pub async fn transaction_get(&self, txid: &MalFixTxid) -> Result<Transaction, Error> {
+        let txid = *txid;
+        self.call(move |c| c.transaction_get(&txid)).await
+    }
+
+    /// Get the history of the given script
+    pub async fn script_get_history(&self, script: &Script) -> Result<Vec<GetHistoryRes>, Error> {
+        let script = script.to_owned();
+        self.call(move |c| c.script_get_history(&script)).await
+    }
+}
+
+/// Esplora client usable from async code, see [crate::esplora::EsploraClient].
+///
+/// Calls are executed on the tokio blocking thread pool, so that they never block the runtime.
+#[cfg(feature = "esplora")]
+#[derive(Debug, Clone)]
+pub struct AsyncEsploraClient {
+    inner: crate::esplora::EsploraClient,
+}
+
+#[cfg(feature = "esplora")]
+impl AsyncEsploraClient {
+    /// Run `f` with the underlying blocking client on the tokio blocking thread pool
+    pub async fn call<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&crate::esplora::EsploraClient) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        spawn_blocking(move || f(&inner)).await
+    }
+
+    /// Height of the last indexed block
+    pub async fn tip_height(&self) -> Result<u32, Error> {
+        self.call(|c| c.tip_height()).await
+    }
+
+    /// Transaction with the given id, `None` if unknown
+    pub async fn tx(&self, txid: &MalFixTxid) -> Result<Option<Transaction>, Error> {
+        let txid = *txid;
+        self.call(move |c| c.tx(&txid)).await
+    }
+}
+
+pub(crate) async fn spawn_blocking<T, E, F>(f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+    E: Into<Error> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::Io(io::Error::new(io::ErrorKind::Other, e)))?
+        .map_err(Into::into)
+}
+
+/// Struct representing the electrs process spawned by tokio, the async variant of
+/// [crate::ElectrsD]
+pub struct AsyncElectrsD {
+    /// Process child handle, used to terminate the process when this struct is dropped
+    process: Child,
+    /// Electrum client connected to the electrs process
+    pub client: AsyncClient,
+    /// Work directory, where the electrs stores indexes and other stuffs.
+    work_dir: DataDir,
+    /// Address to connect to the electrum protocol (tcp)
+    electrum_addr: SocketAddr,
+    /// Address to connect to the esplora protocol (http)
+    esplora_addr: Option<SocketAddr>,
+    /// Address to scrape the prometheus monitoring endpoint (http)
+    monitoring_addr: Option<SocketAddr>,
+    /// Last lines of the electrs output
+    logs: Arc<LogBuffer>,
+    /// Default timeout of the `wait_*` methods
+    wait_timeout: Duration,
+    /// Polling strategy of the `wait_*` methods
+    backoff: Backoff,
+    /// See [Conf::shutdown_timeout]
+    shutdown_timeout: Duration,
+    /// Fault-injecting proxy in front of the electrum endpoint
+    proxy: Option<Proxy>,
+    /// Fault-injecting proxy between electrs and the tapyrusd rpc port
+    daemon_rpc_proxy: Option<Proxy>,
+    /// Fault-injecting proxy between electrs and the tapyrusd p2p port
+    daemon_p2p_proxy: Option<Proxy>,
+}
+
+impl AsyncElectrsD {
+    /// Create a new electrs process connected with the given tapyrusd and default args.
+    pub async fn new<S: AsRef<OsStr>>(exe: S, tapyrusd: &TapyrusD) -> anyhow::Result<Self> {
+        Self::with_conf(exe, tapyrusd, &Conf::default()).await
+    }
+
+    /// Create a new electrs process using given [Conf] connected with the given tapyrusd.
+    ///
+    /// Note the few calls made to tapyrusd to prepare the launch are blocking.
+    ///
+    /// The TLS endpoint is not available, [Error::UnsupportedOption] is returned if
+    /// `tls_enabled` is set.
+    pub async fn with_conf<S: AsRef<OsStr>>(
+        exe: S,
+        tapyrusd: &TapyrusD,
+        conf: &Conf,
+    ) -> anyhow::Result<Self> {
+        #[cfg(feature = "tls")]
+        if conf.tls_enabled {
+            return Err(Error::UnsupportedOption("tls_enabled").into());
+        }
+        let mut attempts = conf.attempts;
+        // the startup timeout is an overall deadline shared by all the attempts
+        let start = Instant::now();
+        loop {
+            let launch = Launch::new(tapyrusd, conf)?;
+            let mut pending = launch.endpoints();
+            let Launch {
+                args,
+                work_dir,
+                electrum_addr,
+                esplora_addr,
+                monitoring_addr,
+                daemon_rpc_proxy,
+                daemon_p2p_proxy,
+            } = launch;
+
+            let mut command = Command::new(exe.as_ref());
+            command
+                .args(&args)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            #[cfg(target_os = "linux")]
+            let spawned = if conf.kill_on_parent_death {
+                // SAFETY: the hook only calls async-signal-safe functions
+                unsafe {
+                    command.pre_exec(crate::parent_death_signal());
+                }
+                // forked by the spawner thread, registered with the current runtime
+                let runtime = tokio::runtime::Handle::current();
+                spawn_blocking(move || {
+                    crate::on_spawner_thread(move || {
+                        let _runtime = runtime.enter();
+                        command.spawn()
+                    })
+                })
+                .await
+            } else {
+                command.spawn().map_err(Error::from)
+            };
+            #[cfg(not(target_os = "linux"))]
+            let spawned = command.spawn().map_err(Error::from);
+            let mut process =
+                spawned.with_context(|| format!("Error while executing {:?}", exe.as_ref()))?;
+
+            let logs = LogBuffer::new(conf.logs_capacity);
+            if let Some(stdout) = process.stdout.take() {
+                logs.capture_async(stdout, conf.view_stderr);
+            }
+            if let Some(stderr) = process.stderr.take() {
+                logs.capture_async(stderr, conf.view_stderr);
+            }
+
+            let status = loop {
+                if let Some(status) = process.try_wait()? {
+                    break status;
+                }
+                pending = spawn_blocking(move || {
+                    pending.retain(|(endpoint, addr)| !endpoint.is_ready(addr));
+                    Ok::<_, Error>(pending)
+                })
+                .await?;
+                if pending.is_empty() {
+                    let connected = async {
+                        let client = AsyncClient::connect(electrum_addr).await?;
+                        let proxy = if conf.proxy_enabled {
+                            Some(Proxy::bind(electrum_addr, conf.bind_addr)?)
+                        } else {
+                            None
+                        };
+                        Ok::<_, Error>((client, proxy))
+                    }
+                    .await;
+                    let (client, proxy) = match connected {
+                        Ok(connected) => connected,
+                        Err(e) => {
+                            // a failure must not leave the ready electrs running
+                            let _ = process.kill().await;
+                            return Err(e.into());
+                        }
+                    };
+                    return Ok(AsyncElectrsD {
+                        process,
+                        client,
+                        work_dir,
+                        electrum_addr,
+                        esplora_addr,
+                        monitoring_addr,
+                        logs,
+                        wait_timeout: conf.wait_timeout,
+                        backoff: conf.backoff,
+                        shutdown_timeout: conf.shutdown_timeout,
+                        proxy,
+                        daemon_rpc_proxy,
+                        daemon_p2p_proxy,
+                    });
+                }
+                let elapsed = start.elapsed();
+                if elapsed >= conf.startup_timeout {
+                    let (endpoint, addr) = pending.remove(0);
+                    error!(
+                        "{} endpoint at {} not ready after {:?}",
+                        endpoint, addr, elapsed
+                    );
+                    let _ = process.kill().await;
+                    return Err(Error::StartupTimeout {
+                        endpoint,
+                        addr,
+                        elapsed,
+                        logs: logs.tail(ERROR_LOG_LINES),
+                    }
+                    .into());
+                }
+                tokio::time::sleep(Duration::from_millis(250)).await;
+            };
+
+            if attempts > 0 {
+                warn!("early exit with: {:?}. Trying to launch again ({} attempts remaining), maybe some other process used our available port", status, attempts);
+                attempts -= 1;
+                continue;
+            }
+            error!("early exit with: {:?}", status);
+            let logs = Arc::clone(&logs);
+            let logs = spawn_blocking(move || {
+                logs.wait_closed(Duration::from_secs(1));
+                Ok::<_, Error>(logs.tail(ERROR_LOG_LINES))
+            })
+            .await?;
+            return Err(Error::EarlyExit { status, logs }.into());
+        }
+    }
+
+    /// triggers electrs sync by sending the `SIGUSR1` signal, useful to call after a block for example
+    #[cfg(not(target_os = "windows"))]
+    pub fn trigger(&self) -> anyhow::Result<()> {
+        if let Some(id) = self.process.id() {
+            nix::sys::signal::kill(
+                nix::unistd::Pid::from_raw(id as i32),
+                nix::sys::signal::SIGUSR1,
+            )?;
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "windows")]
+    pub fn trigger(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Return the current workdir path of the running electrs
+    pub fn workdir(&self) -> PathBuf {
+        self.work_dir.path()
+    }
+
+    /// Address to connect to the electrum protocol
+    pub fn electrum_addr(&self) -> SocketAddr {
+        self.electrum_addr
+    }
+
+    /// Url to connect to the electrum protocol, like `tcp://127.0.0.1:50001`
+    pub fn electrum_url(&self) -> String {
+        format!("tcp://{}", self.electrum_addr)
+    }
+
+    /// Address to connect to the esplora protocol, `None` if [Conf::http_enabled] is not set
+    pub fn esplora_addr(&self) -> Option<SocketAddr> {
+        self.esplora_addr
+    }
+
+    /// Base url of the esplora REST API, like `http://127.0.0.1:3002`, `None` if
+    /// [Conf::http_enabled] is not set
+    pub fn esplora_base_url(&self) -> Option<String> {
+        self.esplora_addr.map(|addr| format!("http://{}", addr))
+    }
+
+    /// Fault-injecting proxy in front of the electrum endpoint, see [crate::ElectrsD::proxy]
+    pub fn proxy(&self) -> Option<&Proxy> {
+        self.proxy.as_ref()
+    }
+
+    /// Fault-injecting proxy between electrs and the tapyrusd rpc port, see
+    /// [crate::ElectrsD::daemon_rpc_proxy]
+    pub fn daemon_rpc_proxy(&self) -> Option<&Proxy> {
+        self.daemon_rpc_proxy.as_ref()
+    }
+
+    /// Fault-injecting proxy between electrs and the tapyrusd p2p port, see
+    /// [crate::ElectrsD::daemon_p2p_proxy]
+    pub fn daemon_p2p_proxy(&self) -> Option<&Proxy> {
+        self.daemon_p2p_proxy.as_ref()
+    }
+
+    /// Address of the prometheus monitoring endpoint, `None` if [Conf::monitoring_enabled] is
+    /// not set
+    pub fn monitoring_addr(&self) -> Option<SocketAddr> {
+        self.monitoring_addr
+    }
+
+    /// Scrape the prometheus monitoring endpoint, see [crate::ElectrsD::metrics]
+    pub async fn metrics(&self) -> Result<HashMap<String, f64>, Error> {
+        let addr = self.monitoring_addr.ok_or(Error::MonitoringDisabled)?;
+        spawn_blocking(move || crate::metrics::scrape(&addr)).await
+    }
+
+    /// Client of the esplora endpoint, `None` if [Conf::http_enabled] is not set
+    #[cfg(feature = "esplora")]
+    pub fn esplora_client(&self) -> Option<AsyncEsploraClient> {
+        self.esplora_base_url().map(|url| AsyncEsploraClient {
+            inner: crate::esplora::EsploraClient::new(url),
+        })
+    }
+
+    /// Return the electrs output lines kept in memory, oldest first.
+    pub fn logs(&self) -> Vec<String> {
+        self.logs.lines()
+    }
+
+    /// Return the last `n` electrs output lines, oldest first
+    pub fn tail_logs(&self, n: usize) -> Vec<String> {
+        self.logs.tail(n)
+    }
+
+    /// Wait up to `timeout` for electrs to output a line matching the `pattern` regex and
+    /// return the line, see [crate::ElectrsD::wait_for_log]
+    pub async fn wait_for_log(&self, pattern: &str, timeout: Duration) -> Result<String, Error> {
+        let regex = Regex::new(pattern)?;
+        let start = Instant::now();
+        let logs = Arc::clone(&self.logs);
+        let line = spawn_blocking(move || Ok::<_, Error>(logs.wait_for(&regex, timeout))).await?;
+        line.ok_or_else(|| log_not_found(&self.logs, pattern, start.elapsed()))
+    }
+
+    fn timeout_error(&self, what: String, elapsed: Duration) -> Error {
+        Error::Timeout {
+            what,
+            elapsed,
+            logs: self.tail_logs(ERROR_LOG_LINES),
+        }
+    }
+
+    /// Call `f` following the configured [Backoff] until it returns `Some` or an error, or
+    /// until `timeout` elapsed returning [Error::Timeout]
+    async fn poll_until<T, F, Fut>(
+        &self,
+        what: &str,
+        timeout: Duration,
+        mut f: F,
+    ) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<Option<T>, Error>>,
+    {
+        let start = Instant::now();
+        let mut interval = self.backoff.initial;
+        loop {
+            if let Some(result) = f().await? {
+                return Ok(result);
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Err(self.timeout_error(what.to_string(), elapsed));
+            }
+            tokio::time::sleep(interval.min(timeout - elapsed)).await;
+            interval = self.backoff.next(interval);
+        }
+    }
+
+    /// wait up to [Conf::wait_timeout] the electrum server has indexed up to the given height.
+    pub async fn wait_height(&self, height: usize) -> Result<(), Error> {
+        self.wait_height_timeout(height, self.wait_timeout).await
+    }
+
+    /// wait up to `timeout` the electrum server has indexed up to the given height.
+    pub async fn wait_height_timeout(&self, height: usize, timeout: Duration) -> Result<(), Error> {
+        self.poll_until(&format!("height {}", height), timeout, || {
+            self.client
+                .call(move |c| Ok::<_, Error>(c.block_header_raw(height).ok().map(|_| ())))
+        })
+        .await
+    }
+
+    /// wait up to [Conf::wait_timeout] the electrum server has fully indexed the given transaction
+    pub async fn wait_tx(&self, txid: &MalFixTxid) -> Result<(), Error> {
+        self.wait_tx_timeout(txid, self.wait_timeout).await
+    }
+
+    /// wait up to `timeout` the electrum server has fully indexed the given transaction, see
+    /// [crate::ElectrsD::wait_tx_timeout]
+    pub async fn wait_tx_timeout(&self, txid: &MalFixTxid, timeout: Duration) -> Result<(), Error> {
+        let txid = *txid;
+        self.poll_until(&format!("transaction {}", txid), timeout, || {
+            self.client
+                .call(move |c| Ok::<_, Error>(ext::tx_history(c, &txid)?.map(|_| ())))
+        })
+        .await
+    }
+
+    /// terminate the electrs process, see [AsyncElectrsD::shutdown] with
+    /// [Conf::shutdown_timeout]
+    pub async fn kill(&mut self) -> anyhow::Result<()> {
+        self.shutdown(self.shutdown_timeout).await?;
+        Ok(())
+    }
+
+    /// Ask electrs to terminate with `SIGINT` and wait up to `timeout` for it to exit, then kill
+    /// it with `SIGKILL`, see [crate::ElectrsD::shutdown]
+    pub async fn shutdown(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        if let Some(status) = self.process.try_wait()? {
+            return Ok(status);
+        }
+        self.inner_kill()?;
+        match tokio::time::timeout(timeout, self.process.wait()).await {
+            Ok(status) => Ok(status?),
+            Err(_) => {
+                warn!(
+                    "electrs not terminated after {:?}, sending SIGKILL",
+                    timeout
+                );
+                // the process may exit meanwhile, the kill then fails but wait succeeds
+                let _ = self.process.start_kill();
+                Ok(self.process.wait().await?)
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    fn inner_kill(&mut self) -> Result<(), Error> {
+        // Send SIGINT signal to electrsd
+        if let Some(id) = self.process.id() {
+            nix::sys::signal::kill(
+                nix::unistd::Pid::from_raw(id as i32),
+                nix::sys::signal::SIGINT,
+            )?;
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "windows")]
+    fn inner_kill(&mut self) -> Result<(), Error> {
+        Ok(self.process.start_kill()?)
+    }
+}
+
+impl Drop for AsyncElectrsD {
+    fn drop(&mut self) {
+        // waiting is not possible here, a thread reaps electrs after sending `SIGKILL` if it
+        // doesn't exit within the shutdown timeout, call `shutdown` to wait for it
+        if let Ok(Some(_)) = self.process.try_wait() {
+            return;
+        }
+        if let Err(e) = self.inner_kill() {
+            error!("failed to shutdown electrs: {}", e);
+        }
+        #[cfg(not(target_os = "windows"))]
+        if let Some(pid) = self.process.id() {
+            let timeout = self.shutdown_timeout;
+            std::thread::spawn(move || reap(pid, timeout));
+        }
+    }
+}
+
+/// Wait up to `timeout` for the process `pid` to exit, then kill it with `SIGKILL`, reaping it in
+/// both cases. The tokio [Child] doesn't reap a process dropped while running.
+#[cfg(not(target_os = "windows"))]
+fn reap(pid: u32, timeout: Duration) {
+    use nix::sys::signal::{kill, SIGKILL};
+    use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+    use nix::unistd::Pid;
+
+    let pid = Pid::from_raw(pid as i32);
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) => std::thread::sleep(Duration::from_millis(50)),
+            // exited and reaped
+            _ => return,
+        }
+    }
+    warn!(
+        "electrs not terminated after {:?}, sending SIGKILL",
+        timeout
+    );
+    let _ = kill(pid, SIGKILL);
+    let _ = waitpid(pid, None);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::setup_tapyrusd;
+    use crate::AsyncElectrsD;
+    use log::{log_enabled, Level};
+    use std::time::Duration;
+    use tapyrusd::get_private_key;
+    use tapyrusd::tapyruscore_rpc::RpcApi;
+
+    #[tokio::test]
+    async fn test_async_electrsd() {
+        let (electrs_exe, tapyrusd) = setup_tapyrusd();
+        let conf = crate::Conf {
+            view_stderr: log_enabled!(Level::Debug),
+            ..Default::default()
+        };
+        let mut electrsd = AsyncElectrsD::with_conf(&electrs_exe, &tapyrusd, &conf)
+            .await
+            .unwrap();
+        let header = electrsd.client.block_headers_subscribe().await.unwrap();
+        assert_eq!(header.height, 1);
+
+        let address = tapyrusd
+            .client
+            .get_new_address(None)
+            .unwrap()
+            .assume_checked();
+        tapyrusd
+            .client
+            .generate_to_address(10, &address, get_private_key())
+            .unwrap();
+        electrsd.trigger().unwrap();
+        electrsd
+            .wait_height_timeout(11, Duration::from_secs(60))
+            .await
+            .unwrap();
+        let header = electrsd.client.block_headers_subscribe().await.unwrap();
+        assert_eq!(header.height, 11);
+
+        electrsd.kill().await.unwrap();
+        assert!(electrsd.client.ping().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_async_drop() {
+        let (electrs_exe, tapyrusd) = setup_tapyrusd();
+        let electrsd = AsyncElectrsD::new(&electrs_exe, &tapyrusd).await.unwrap();
+        let electrum_addr = electrsd.electrum_addr();
+        drop(electrsd);
+
+        let start = std::time::Instant::now();
+        while std::net::TcpStream::connect(electrum_addr).is_ok() {
+            assert!(start.elapsed() < Duration::from_secs(20));
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn test_async_tls_unsupported() {
+        let (electrs_exe, tapyrusd) = setup_tapyrusd();
+        let conf = crate::Conf {
+            tls_enabled: true,
+            ..Default::default()
+        };
+        let err = AsyncElectrsD::with_conf(&electrs_exe, &tapyrusd, &conf)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::Error>(),
+            Some(crate::Error::UnsupportedOption("tls_enabled"))
+        ));
+    }
+}
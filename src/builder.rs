@@ -0,0 +1,337 @@
+//! Builder for the electrs configuration
+//!
+
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::{Backoff, Conf, Error};
+
+/// Flags automatically initialized by [crate::ElectrsD::with_conf], passing them again would
+/// make electrs fail because of the duplicated argument.
+const RESERVED_ARGS: &[&str] = &[
+    "--db-dir",
+    "--network",
+    "--cookie",
+    "--cookie-file",
+    "--daemon-rpc-addr",
+    "--daemon-p2p-addr",
+    "--jsonrpc-import",
+    "--electrum-rpc-addr",
+    "--monitoring-addr",
+    "--http-addr",
+];
+
+/// Returns [Error::ReservedArg] if any of the given args is automatically initialized
+pub(crate) fn check_args<S: AsRef<str>>(args: &[S]) -> Result<(), Error> {
+    for arg in args {
+        let arg = arg.as_ref();
+        let flag = arg.split('=').next().unwrap_or(arg);
+        if RESERVED_ARGS.contains(&flag) {
+            return Err(Error::ReservedArg(arg.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Builder of [Conf] with typed electrs options.
+///
+/// ```
+/// let conf = electrsd::Conf::builder()
+///     .verbosity(2)
+///     .lightmode(true)
+///     .utxos_limit(100)
+///     .build()
+///     .unwrap();
+/// assert_eq!(conf.args, vec!["-vv", "--lightmode", "--utxos-limit", "100"]);
+///
+/// assert!(electrsd::Conf::builder().arg("--db-dir=/tmp").build().is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConfBuilder {
+    conf: Conf,
+    verbosity: Option<u8>,
+    timestamp: bool,
+    lightmode: bool,
+    address_search: bool,
+    index_unspendables: bool,
+    utxos_limit: Option<usize>,
+    electrum_txs_limit: Option<usize>,
+    electrum_banner: Option<String>,
+    cors: Option<String>,
+    precache_scripts: Option<PathBuf>,
+    extra_args: Vec<String>,
+}
+
+impl ConfBuilder {
+    /// Create a builder initialized with [Conf::default]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Log verbosity, number of `-v` passed to electrs, overrides the default one
+    pub fn verbosity(mut self, level: u8) -> Self {
+        self.verbosity = Some(level);
+        self
+    }
+
+    /// Prepend log lines with a timestamp (`--timestamp`)
+    pub fn timestamp(mut self, enabled: bool) -> Self {
+        self.timestamp = enabled;
+        self
+    }
+
+    /// Run in light mode, fetching transactions from tapyrusd instead of indexing them (`--lightmode`)
+    pub fn lightmode(mut self, enabled: bool) -> Self {
+        self.lightmode = enabled;
+        self
+    }
+
+    /// Enable prefix address search (`--address-search`)
+    pub fn address_search(mut self, enabled: bool) -> Self {
+        self.address_search = enabled;
+        self
+    }
+
+    /// Enable indexing of provably unspendable outputs (`--index-unspendables`)
+    pub fn index_unspendables(mut self, enabled: bool) -> Self {
+        self.index_unspendables = enabled;
+        self
+    }
+
+    /// Maximum number of utxos to process per address (`--utxos-limit`)
+    pub fn utxos_limit(mut self, limit: usize) -> Self {
+        self.utxos_limit = Some(limit);
+        self
+    }
+
+    /// Maximum number of transactions returned by electrum history queries (`--electrum-txs-limit`)
+    pub fn electrum_txs_limit(mut self, limit: usize) -> Self {
+        self.electrum_txs_limit = Some(limit);
+        self
+    }
+
+    /// Welcome banner returned by the electrum server (`--electrum-banner`)
+    pub fn electrum_banner<S: Into<String>>(mut self, banner: S) -> Self {
+        self.electrum_banner = Some(banner.into());
+        self
+    }
+
+    /// Origins allowed to make cross-site requests to the esplora endpoint (`--cors`)
+    pub fn cors<S: Into<String>>(mut self, origins: S) -> Self {
+        self.cors = Some(origins.into());
+        self
+    }
+
+    /// Path of a file with scripts to precache on startup (`--precache-scripts`)
+    pub fn precache_scripts<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.precache_scripts = Some(path.into());
+        self
+    }
+
+    /// Append a raw command line argument, reserved flags are rejected by [ConfBuilder::build]
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.extra_args.push(arg.into());
+        self
+    }
+
+    /// if `true` electrsd log output is printed on stderr, besides being captured in memory
+    pub fn view_stderr(mut self, enabled: bool) -> Self {
+        self.conf.view_stderr = enabled;
+        self
+    }
+
+    /// Maximum number of electrsd output lines kept in memory
+    pub fn logs_capacity(mut self, capacity: usize) -> Self {
+        self.conf.logs_capacity = capacity;
+        self
+    }
+
+    /// if `true` electrsd exposes an esplora endpoint
+    pub fn http_enabled(mut self, enabled: bool) -> Self {
+        self.conf.http_enabled = enabled;
+        self
+    }
+
+    /// Address the endpoints bind to, see [Conf::bind_addr]
+    pub fn bind_addr<A: Into<IpAddr>>(mut self, addr: A) -> Self {
+        self.conf.bind_addr = addr.into();
+        self
+    }
+
+    /// if `false` the monitoring endpoint is not reported nor probed, see
+    /// [Conf::monitoring_enabled]
+    pub fn monitoring_enabled(mut self, enabled: bool) -> Self {
+        self.conf.monitoring_enabled = enabled;
+        self
+    }
+
+    /// if `true` a fault-injecting proxy is put in front of the electrum endpoint, see
+    /// [Conf::proxy_enabled]
+    pub fn proxy_enabled(mut self, enabled: bool) -> Self {
+        self.conf.proxy_enabled = enabled;
+        self
+    }
+
+    /// if `true` electrs connects to tapyrusd through fault-injecting proxies, see
+    /// [Conf::daemon_proxy_enabled]
+    pub fn daemon_proxy_enabled(mut self, enabled: bool) -> Self {
+        self.conf.daemon_proxy_enabled = enabled;
+        self
+    }
+
+    /// if `true` a TLS endpoint is put in front of the electrum one, see [Conf::tls_enabled]
+    #[cfg(feature = "tls")]
+    pub fn tls_enabled(mut self, enabled: bool) -> Self {
+        self.conf.tls_enabled = enabled;
+        self
+    }
+
+    /// Must match tapyrusd network
+    pub fn network<S: Into<String>>(mut self, network: S) -> Self {
+        self.conf.network = network.into();
+        self
+    }
+
+    /// Temporary directory path, see [Conf::tmpdir]
+    pub fn tmpdir<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.conf.tmpdir = Some(path.into());
+        self
+    }
+
+    /// Persistent directory path, see [Conf::staticdir]
+    pub fn staticdir<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.conf.staticdir = Some(path.into());
+        self
+    }
+
+    /// Default timeout of the `wait_*` methods not taking an explicit one
+    pub fn wait_timeout(mut self, timeout: Duration) -> Self {
+        self.conf.wait_timeout = timeout;
+        self
+    }
+
+    /// Polling strategy of the `wait_*` methods
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.conf.backoff = backoff;
+        self
+    }
+
+    /// Maximum time to wait for every enabled endpoint to accept requests after the process is spawned
+    pub fn startup_timeout(mut self, timeout: Duration) -> Self {
+        self.conf.startup_timeout = timeout;
+        self
+    }
+
+    /// if `false` electrs is not killed when the spawning thread exits, see
+    /// [Conf::kill_on_parent_death]
+    pub fn kill_on_parent_death(mut self, enabled: bool) -> Self {
+        self.conf.kill_on_parent_death = enabled;
+        self
+    }
+
+    /// Maximum time to wait for electrs to exit before killing it, see [Conf::shutdown_timeout]
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.conf.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Build the [Conf], returns an error if a reserved flag has been given or if both
+    /// `tmpdir` and `staticdir` are set
+    pub fn build(self) -> Result<Conf, Error> {
+        let mut conf = self.conf;
+        if conf.tmpdir.is_some() && conf.staticdir.is_some() {
+            return Err(Error::BothDirsSpecified);
+        }
+        check_args(&self.extra_args)?;
+
+        let mut args = match self.verbosity {
+            Some(0) => vec![],
+            Some(level) => vec![format!("-{}", "v".repeat(level as usize))],
+            None => conf.args,
+        };
+        if self.timestamp {
+            args.push("--timestamp".to_string());
+        }
+        if self.lightmode {
+            args.push("--lightmode".to_string());
+        }
+        if self.address_search {
+            args.push("--address-search".to_string());
+        }
+        if self.index_unspendables {
+            args.push("--index-unspendables".to_string());
+        }
+        if let Some(limit) = self.utxos_limit {
+            args.push("--utxos-limit".to_string());
+            args.push(limit.to_string());
+        }
+        if let Some(limit) = self.electrum_txs_limit {
+            args.push("--electrum-txs-limit".to_string());
+            args.push(limit.to_string());
+        }
+        if let Some(banner) = self.electrum_banner {
+            args.push("--electrum-banner".to_string());
+            args.push(banner);
+        }
+        if let Some(cors) = self.cors {
+            args.push("--cors".to_string());
+            args.push(cors);
+        }
+        if let Some(path) = self.precache_scripts {
+            args.push("--precache-scripts".to_string());
+            args.push(path.display().to_string());
+        }
+        args.extend(self.extra_args);
+        conf.args = args;
+
+        Ok(conf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Conf, Error};
+
+    #[test]
+    fn test_builder_reserved_args() {
+        for arg in ["--db-dir", "--http-addr=127.0.0.1:3000", "--cookie"] {
+            match Conf::builder().arg(arg).build() {
+                Err(Error::ReservedArg(a)) => assert_eq!(a, arg),
+                _ => panic!("{} must be rejected", arg),
+            }
+        }
+        assert!(Conf::builder().arg("--lightmode").build().is_ok());
+    }
+
+    #[test]
+    fn test_builder_args() {
+        let conf = Conf::builder()
+            .verbosity(0)
+            .timestamp(true)
+            .electrum_txs_limit(10)
+            .arg("--electrum-rpc-logging=full")
+            .network("prod")
+            .build()
+            .unwrap();
+        assert_eq!(
+            conf.args,
+            vec![
+                "--timestamp",
+                "--electrum-txs-limit",
+                "10",
+                "--electrum-rpc-logging=full"
+            ]
+        );
+        assert_eq!(conf.network, "prod");
+
+        let conf = Conf::builder().build().unwrap();
+        assert_eq!(conf, Conf::default());
+
+        assert!(Conf::builder()
+            .tmpdir("/tmp")
+            .staticdir("/tmp")
+            .build()
+            .is_err());
+    }
+}
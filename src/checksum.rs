@@ -0,0 +1,70 @@
+// Included by `build.rs` too, so it must not depend on anything but `std`.
+
+/// Return the expected hex encoded sha256 of `filename` from `table`, the content of the `sha256`
+/// file made of `<sha256>  <filename>` lines. Empty lines and lines starting with `#` are skipped.
+pub fn expected_sha256<'a>(table: &'a str, filename: &str) -> Option<&'a str> {
+    table
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once("  "))
+        .find(|(_, name)| name.trim() == filename)
+        .map(|(hash, _)| hash.trim())
+}
+
+/// Check the hex encoded sha256 `actual` of the archive `filename` against the one in `table`,
+/// returns a message explaining the failure otherwise
+pub fn verify_sha256(table: &str, filename: &str, actual: &str) -> Result<(), String> {
+    match expected_sha256(table, filename) {
+        Some(expected) if expected.eq_ignore_ascii_case(actual) => Ok(()),
+        Some(expected) => Err(format!(
+            "sha256 mismatch for {}: expected {} but got {}, the archive may be corrupted or tampered",
+            filename, expected, actual
+        )),
+        None => Err(format!(
+            "no sha256 listed for {} in the `sha256` file, the archive hash is {}: once verified \
+             add the line `{}  {}` to the `sha256` file",
+            filename, actual, actual, filename
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{expected_sha256, verify_sha256};
+
+    // fake hashes, the real ones are in the `sha256` file
+    const TABLE: &str = "# comment
+00000000000000000000000000000000000000000000000000000000000000ab  esplora-tapyrus-v0.5.0-x86_64-unknown-linux-gnu.tar.gz
+
+00000000000000000000000000000000000000000000000000000000000000cd  esplora-tapyrus-v0.5.1-x86_64-unknown-linux-gnu.tar.gz
+";
+
+    #[test]
+    fn test_expected_sha256() {
+        assert_eq!(
+            expected_sha256(
+                TABLE,
+                "esplora-tapyrus-v0.5.1-x86_64-unknown-linux-gnu.tar.gz"
+            ),
+            Some("00000000000000000000000000000000000000000000000000000000000000cd")
+        );
+        assert_eq!(expected_sha256(TABLE, "esplora-tapyrus-v0.5.1"), None);
+        assert_eq!(expected_sha256(TABLE, "# comment"), None);
+    }
+
+    #[test]
+    fn test_verify_sha256() {
+        let filename = "esplora-tapyrus-v0.5.0-x86_64-unknown-linux-gnu.tar.gz";
+        let hash = "00000000000000000000000000000000000000000000000000000000000000ab";
+        assert!(verify_sha256(TABLE, filename, hash).is_ok());
+        assert!(verify_sha256(TABLE, filename, &hash.to_uppercase()).is_ok());
+
+        let other = "00000000000000000000000000000000000000000000000000000000000000cd";
+        let err = verify_sha256(TABLE, filename, other).unwrap_err();
+        assert!(err.contains("mismatch"));
+
+        let err = verify_sha256(TABLE, "unknown.tar.gz", hash).unwrap_err();
+        assert!(err.contains(&format!("{}  unknown.tar.gz", hash)));
+    }
+}
@@ -17,8 +17,13 @@ pub enum Error {
     #[cfg(not(target_os = "windows"))]
     Nix(nix::Error),
 
-    /// Wrapper of early exit status
-    EarlyExit(std::process::ExitStatus),
+    /// Returned when electrs exits before being ready, with its last output lines
+    EarlyExit {
+        /// Exit status of the electrs process
+        status: std::process::ExitStatus,
+        /// Last lines of the electrs output
+        logs: Vec<String>,
+    },
 
     /// Returned when both tmpdir and staticdir is specified in `Conf` options
     BothDirsSpecified,
@@ -29,6 +34,64 @@ pub enum Error {
 
     /// Returned if both env vars `ELECTRS_EXEC` and `ELECTRS_EXE` are found
     BothEnvVars,
+
+    /// Returned when an endpoint doesn't accept requests within [crate::Conf::startup_timeout]
+    StartupTimeout {
+        /// The endpoint not ready
+        endpoint: crate::Endpoint,
+        /// Address of the endpoint
+        addr: std::net::SocketAddr,
+        /// Time waited before giving up
+        elapsed: std::time::Duration,
+        /// Last lines of the electrs output
+        logs: Vec<String>,
+    },
+
+    /// Returned when a wait didn't complete in the given time, with the last electrs output lines
+    Timeout {
+        /// Description of what was awaited
+        what: String,
+        /// Time waited before giving up
+        elapsed: std::time::Duration,
+        /// Last lines of the electrs output
+        logs: Vec<String>,
+    },
+
+    /// Returned when electrs exits, closing its output, while waiting for a log line
+    Exited {
+        /// Description of what was awaited
+        what: String,
+        /// Last lines of the electrs output
+        logs: Vec<String>,
+    },
+
+    /// Wrapper of regex Error
+    Regex(regex::Error),
+
+    /// Wrapper of ureq Error, returned by the esplora client
+    #[cfg(feature = "esplora")]
+    Http(Box<ureq::Error>),
+
+    /// Wrapper of rustls Error, returned by the TLS endpoint
+    #[cfg(feature = "tls")]
+    Rustls(rustls::Error),
+
+    /// Wrapper of rcgen Error, returned when generating the TLS certificate
+    #[cfg(feature = "tls")]
+    Rcgen(rcgen::RcgenError),
+
+    /// Returned when a response of electrs can't be parsed
+    InvalidResponse(String),
+
+    /// Returned when scraping metrics while [crate::Conf::monitoring_enabled] is not set
+    MonitoringDisabled,
+
+    /// Returned when a `Conf` option isn't supported by the chosen launcher, like
+    /// `tls_enabled` with `AsyncElectrsD`
+    UnsupportedOption(&'static str),
+
+    /// Returned when `Conf` args contain a flag that is automatically initialized by [crate::ElectrsD::with_conf]
+    ReservedArg(String),
 }
 
 impl std::error::Error for Error {
@@ -38,6 +101,16 @@ impl std::error::Error for Error {
             Error::Tapyrusd(e) => Some(e),
             Error::ElectrumClient(e) => Some(e),
             Error::TapyrusCoreRpc(e) => Some(e),
+            Error::Regex(e) => Some(e),
+
+            #[cfg(feature = "esplora")]
+            Error::Http(e) => Some(e),
+
+            #[cfg(feature = "tls")]
+            Error::Rustls(e) => Some(e),
+
+            #[cfg(feature = "tls")]
+            Error::Rcgen(e) => Some(e),
 
             #[cfg(not(target_os = "windows"))]
             Error::Nix(e) => Some(e),
@@ -49,8 +122,49 @@ impl std::error::Error for Error {
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Error::EarlyExit { status, logs } => {
+                write!(f, "electrs exited early with {}", status)?;
+                write_logs(f, logs)
+            }
+            Error::StartupTimeout {
+                endpoint,
+                addr,
+                elapsed,
+                logs,
+            } => {
+                write!(
+                    f,
+                    "{} endpoint at {} not ready after {:?}",
+                    endpoint, addr, elapsed
+                )?;
+                write_logs(f, logs)
+            }
+            Error::Timeout {
+                what,
+                elapsed,
+                logs,
+            } => {
+                write!(f, "timeout waiting {} after {:?}", what, elapsed)?;
+                write_logs(f, logs)
+            }
+            Error::Exited { what, logs } => {
+                write!(f, "electrs exited while waiting {}", what)?;
+                write_logs(f, logs)
+            }
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+fn write_logs(f: &mut std::fmt::Formatter<'_>, logs: &[String]) -> std::fmt::Result {
+    if !logs.is_empty() {
+        write!(f, ", last electrs output lines:")?;
+        for line in logs {
+            write!(f, "\n  {}", line)?;
+        }
     }
+    Ok(())
 }
 
 impl From<std::io::Error> for Error {
@@ -77,6 +191,26 @@ impl From<tapyrusd::tapyruscore_rpc::Error> for Error {
     }
 }
 
+impl From<regex::Error> for Error {
+    fn from(e: regex::Error) -> Self {
+        Error::Regex(e)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl From<rustls::Error> for Error {
+    fn from(e: rustls::Error) -> Self {
+        Error::Rustls(e)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl From<rcgen::RcgenError> for Error {
+    fn from(e: rcgen::RcgenError) -> Self {
+        Error::Rcgen(e)
+    }
+}
+
 #[cfg(not(target_os = "windows"))]
 impl From<nix::Error> for Error {
     fn from(e: nix::Error) -> Self {
@@ -0,0 +1,400 @@
+//! Client of the esplora REST API exposed when [crate::Conf::http_enabled] is set, enabled by
+//! the `esplora` feature
+//!
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::Read;
+use std::str::FromStr;
+use std::time::Duration;
+
+use electrum_client::tapyrus::consensus::{deserialize, serialize};
+use electrum_client::tapyrus::hashes::{sha256, Hash};
+use electrum_client::tapyrus::script::color_identifier::ColorIdentifier;
+use electrum_client::tapyrus::{Address, Block, BlockHash, MalFixTxid, Script, Transaction};
+use serde::{de, Deserialize, Deserializer};
+
+use crate::{ElectrsD, Error};
+
+/// Confirmation status of a transaction
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TxStatus {
+    /// Whether the transaction is in a block
+    pub confirmed: bool,
+    /// Height of the block including the transaction
+    pub block_height: Option<u32>,
+    /// Hash of the block including the transaction
+    #[serde(default, deserialize_with = "option_from_str")]
+    pub block_hash: Option<BlockHash>,
+}
+
+/// Funded and spent outputs statistics of an address or script
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Stats {
+    /// Number of outputs funding the script
+    pub funded_txo_count: u64,
+    /// Sum of the outputs funding the script
+    pub funded_txo_sum: u64,
+    /// Number of spent outputs of the script
+    pub spent_txo_count: u64,
+    /// Sum of the spent outputs of the script
+    pub spent_txo_sum: u64,
+    /// Number of transactions touching the script
+    pub tx_count: u64,
+}
+
+/// Statistics of an address or script, confirmed and in mempool
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ScriptStats {
+    /// Statistics of the confirmed transactions
+    pub chain_stats: Stats,
+    /// Statistics of the mempool transactions
+    pub mempool_stats: Stats,
+}
+
+/// Unspent output of an address or script
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Utxo {
+    /// Id of the transaction creating the output
+    #[serde(deserialize_with = "from_str")]
+    pub txid: MalFixTxid,
+    /// Index of the output in the transaction
+    pub vout: u32,
+    /// Amount of the output, in tapyrus or in colored coin units
+    pub value: u64,
+    /// Color id of the output, `None` for uncolored outputs
+    #[serde(default, alias = "colorid", deserialize_with = "option_from_str")]
+    pub color_id: Option<ColorIdentifier>,
+    /// Confirmation status of the transaction creating the output
+    pub status: TxStatus,
+}
+
+/// Mempool statistics
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct MempoolStats {
+    /// Number of transactions in the mempool
+    pub count: u64,
+    /// Total size of the transactions in the mempool
+    pub vsize: u64,
+    /// Total fees of the transactions in the mempool
+    pub total_fee: u64,
+}
+
+/// Blocking client of the esplora REST API
+#[derive(Debug, Clone)]
+pub struct EsploraClient {
+    agent: ureq::Agent,
+    base_url: String,
+}
+
+impl EsploraClient {
+    /// Create a client of the esplora endpoint at `base_url`, like `http://127.0.0.1:3002`
+    pub fn new<S: Into<String>>(base_url: S) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(30))
+            .build();
+        EsploraClient {
+            agent,
+            base_url: base_url.into(),
+        }
+    }
+
+    /// Base url of the esplora endpoint
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn get(&self, path: &str) -> Result<Option<ureq::Response>, Error> {
+        match self.agent.get(&format!("{}{}", self.base_url, path)).call() {
+            Ok(response) => Ok(Some(response)),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(Error::Http(Box::new(e))),
+        }
+    }
+
+    fn get_text(&self, path: &str) -> Result<Option<String>, Error> {
+        match self.get(path)? {
+            Some(response) => Ok(Some(response.into_string()?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_parsed<T>(&self, path: &str) -> Result<T, Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let text = self.get_text(path)?.ok_or_else(|| not_found(path))?;
+        text.trim()
+            .parse()
+            .map_err(|e| Error::InvalidResponse(format!("{}: {}", path, e)))
+    }
+
+    fn get_json<T: de::DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        let response = self.get(path)?.ok_or_else(|| not_found(path))?;
+        Ok(response.into_json()?)
+    }
+
+    fn get_decoded<T: electrum_client::tapyrus::consensus::Decodable>(
+        &self,
+        path: &str,
+    ) -> Result<Option<T>, Error> {
+        let response = match self.get(path)? {
+            Some(response) => response,
+            None => return Ok(None),
+        };
+        let mut bytes = vec![];
+        response.into_reader().read_to_end(&mut bytes)?;
+        deserialize(&bytes)
+            .map(Some)
+            .map_err(|e| Error::InvalidResponse(format!("{}: {}", path, e)))
+    }
+
+    /// Height of the last indexed block
+    pub fn tip_height(&self) -> Result<u32, Error> {
+        self.get_parsed("/blocks/tip/height")
+    }
+
+    /// Hash of the last indexed block
+    pub fn tip_hash(&self) -> Result<BlockHash, Error> {
+        self.get_parsed("/blocks/tip/hash")
+    }
+
+    /// Hash of the block at the given height
+    pub fn block_hash(&self, height: u32) -> Result<BlockHash, Error> {
+        self.get_parsed(&format!("/block-height/{}", height))
+    }
+
+    /// Block with the given hash, `None` if unknown
+    pub fn block(&self, hash: &BlockHash) -> Result<Option<Block>, Error> {
+        self.get_decoded(&format!("/block/{}/raw", hash))
+    }
+
+    /// Transaction with the given id, `None` if unknown
+    pub fn tx(&self, txid: &MalFixTxid) -> Result<Option<Transaction>, Error> {
+        self.get_decoded(&format!("/tx/{}/raw", txid))
+    }
+
+    /// Confirmation status of the transaction with the given id
+    pub fn tx_status(&self, txid: &MalFixTxid) -> Result<TxStatus, Error> {
+        self.get_json(&format!("/tx/{}/status", txid))
+    }
+
+    /// Broadcast the given transaction, returns its id
+    pub fn broadcast(&self, tx: &Transaction) -> Result<MalFixTxid, Error> {
+        let hex: String = serialize(tx).iter().map(|b| format!("{:02x}", b)).collect();
+        let response = self
+            .agent
+            .post(&format!("{}/tx", self.base_url))
+            .send_string(&hex)
+            .map_err(|e| Error::Http(Box::new(e)))?;
+        let text = response.into_string()?;
+        text.trim()
+            .parse()
+            .map_err(|e| Error::InvalidResponse(format!("/tx: {}", e)))
+    }
+
+    /// Statistics of the given address
+    pub fn address_stats(&self, address: &Address) -> Result<ScriptStats, Error> {
+        self.get_json(&format!("/address/{}", address))
+    }
+
+    /// Statistics of the given script
+    pub fn scripthash_stats(&self, script: &Script) -> Result<ScriptStats, Error> {
+        self.get_json(&format!("/scripthash/{}", scripthash(script)))
+    }
+
+    /// Unspent outputs of the given address
+    pub fn address_utxos(&self, address: &Address) -> Result<Vec<Utxo>, Error> {
+        self.get_json(&format!("/address/{}/utxo", address))
+    }
+
+    /// Unspent outputs of the given script
+    pub fn scripthash_utxos(&self, script: &Script) -> Result<Vec<Utxo>, Error> {
+        self.get_json(&format!("/scripthash/{}/utxo", scripthash(script)))
+    }
+
+    /// Mempool statistics
+    pub fn mempool(&self) -> Result<MempoolStats, Error> {
+        self.get_json("/mempool")
+    }
+
+    /// Ids of the transactions in the mempool
+    pub fn mempool_txids(&self) -> Result<Vec<MalFixTxid>, Error> {
+        let txids: Vec<String> = self.get_json("/mempool/txids")?;
+        txids
+            .iter()
+            .map(|txid| {
+                txid.parse()
+                    .map_err(|e| Error::InvalidResponse(format!("/mempool/txids: {}", e)))
+            })
+            .collect()
+    }
+
+    /// Fee rate estimates in tapyrus per virtual byte, keyed by confirmation target in blocks
+    pub fn fee_estimates(&self) -> Result<HashMap<u16, f64>, Error> {
+        let estimates: HashMap<String, f64> = self.get_json("/fee-estimates")?;
+        estimates
+            .into_iter()
+            .map(|(target, fee)| {
+                target
+                    .parse()
+                    .map(|target| (target, fee))
+                    .map_err(|e| Error::InvalidResponse(format!("/fee-estimates: {}", e)))
+            })
+            .collect()
+    }
+}
+
+/// The esplora script hash is the sha256 of the script, not reversed unlike electrum one
+fn scripthash(script: &Script) -> sha256::Hash {
+    sha256::Hash::hash(script.as_bytes())
+}
+
+fn not_found(path: &str) -> Error {
+    Error::InvalidResponse(format!("{}: not found", path))
+}
+
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(de::Error::custom)
+}
+
+fn option_from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) => s.parse().map(Some).map_err(de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+impl ElectrsD {
+    /// Client of the esplora endpoint, `None` if [crate::Conf::http_enabled] is not set
+    pub fn esplora_client(&self) -> Option<EsploraClient> {
+        self.esplora_base_url().map(EsploraClient::new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::{send_to_new_address, setup_tapyrusd};
+    use crate::ElectrsD;
+    use electrum_client::tapyrus::script::color_identifier::ColorIdentifier;
+    use electrum_client::tapyrus::{Amount, MalFixTxid};
+    use std::time::Duration;
+    use tapyrusd::tapyruscore_rpc::jsonrpc::serde_json::{json, Value};
+    use tapyrusd::tapyruscore_rpc::RpcApi;
+
+    #[test]
+    fn test_esplora_client() {
+        let (electrs_exe, tapyrusd) = setup_tapyrusd();
+        let conf = crate::Conf {
+            http_enabled: true,
+            ..Default::default()
+        };
+        let electrsd = ElectrsD::with_conf(&electrs_exe, &tapyrusd, &conf).unwrap();
+        let hashes = electrsd.mine_blocks(&tapyrusd, 100, None).unwrap();
+        let client = electrsd.esplora_client().unwrap();
+
+        assert_eq!(client.tip_height().unwrap(), 101);
+        assert_eq!(client.tip_hash().unwrap(), hashes[99]);
+        assert_eq!(client.block_hash(101).unwrap(), hashes[99]);
+        assert!(client.block(&hashes[99]).unwrap().is_some());
+
+        let (address, txid) = send_to_new_address(&tapyrusd, Amount::from_tap(10000));
+        electrsd
+            .wait_tx_timeout(&txid, Duration::from_secs(60))
+            .unwrap();
+
+        assert!(client.tx(&txid).unwrap().is_some());
+        assert!(!client.tx_status(&txid).unwrap().confirmed);
+        assert_eq!(client.mempool_txids().unwrap(), vec![txid]);
+        let utxos = client.scripthash_utxos(&address.script_pubkey()).unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].value, 10000);
+        assert_eq!(utxos[0].color_id, None);
+        let stats = client.address_stats(&address).unwrap();
+        assert_eq!(stats.mempool_stats.funded_txo_sum, 10000);
+    }
+
+    #[test]
+    fn test_esplora_colored_utxo() {
+        let (electrs_exe, tapyrusd) = setup_tapyrusd();
+        let conf = crate::Conf {
+            http_enabled: true,
+            ..Default::default()
+        };
+        let electrsd = ElectrsD::with_conf(&electrs_exe, &tapyrusd, &conf).unwrap();
+        electrsd.mine_blocks(&tapyrusd, 100, None).unwrap();
+        let client = electrsd.esplora_client().unwrap();
+
+        let script_pubkey = |address: &str| -> String {
+            let info: Value = tapyrusd
+                .client
+                .call("getaddressinfo", &[json!(address)])
+                .unwrap();
+            info["scriptPubKey"].as_str().unwrap().to_string()
+        };
+
+        // reissuable token, its color id is derived from the issuer script
+        let issuer = tapyrusd.client.get_new_address(None).unwrap();
+        let issued: Value = tapyrusd
+            .client
+            .call(
+                "issuetoken",
+                &[
+                    json!(1),
+                    json!(100),
+                    json!(script_pubkey(&issuer.assume_checked().to_string())),
+                ],
+            )
+            .unwrap();
+        let color = issued["color"].as_str().unwrap();
+        electrsd.mine_blocks(&tapyrusd, 1, None).unwrap();
+
+        let colored_address: String = tapyrusd
+            .client
+            .call("getnewaddress", &[json!(""), json!(color)])
+            .unwrap();
+        let txid: String = tapyrusd
+            .client
+            .call("transfertoken", &[json!(colored_address), json!(10)])
+            .unwrap();
+        let txid: MalFixTxid = txid.parse().unwrap();
+        electrsd
+            .wait_tx_timeout(&txid, Duration::from_secs(60))
+            .unwrap();
+
+        let colored_script = script_pubkey(&colored_address);
+        let tx = client.tx(&txid).unwrap().unwrap();
+        let output = tx
+            .output
+            .iter()
+            .find(|output| {
+                let hex: String = output
+                    .script_pubkey
+                    .as_bytes()
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect();
+                hex == colored_script
+            })
+            .unwrap();
+        let utxos = client.scripthash_utxos(&output.script_pubkey).unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].value, 10);
+        assert_eq!(
+            utxos[0].color_id,
+            Some(color.parse::<ColorIdentifier>().unwrap())
+        );
+    }
+}
@@ -1,58 +1,497 @@
 //! Extra functions for the electrs rpc
 //!
 
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::ElectrsD;
-use electrum_client::{tapyrus::MalFixTxid, ElectrumApi};
+use crate::{ElectrsD, Error};
+use electrum_client::raw_client::{ElectrumPlaintextStream, RawClient};
+use electrum_client::tapyrus::{MalFixTxid, Script};
+use electrum_client::{ElectrumApi, GetBalanceRes, GetHistoryRes};
+use log::{debug, warn};
+use tapyrusd::anyhow;
+use tapyrusd::tapyruscore_rpc::jsonrpc::serde_json::{self, Value};
+use tapyrusd::tapyruscore_rpc::tapyrus::{Address, BlockHash};
+use tapyrusd::tapyruscore_rpc::RpcApi;
+use tapyrusd::{get_private_key, TapyrusD};
+
+/// Polling strategy used by the `wait_*` methods.
+///
+/// The interval between two attempts starts at `initial` and is multiplied by `multiplier`
+/// after every attempt, without exceeding `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// Interval before the second attempt
+    pub initial: Duration,
+    /// Maximum interval between two attempts
+    pub max: Duration,
+    /// Factor applied to the interval after every attempt, `1` means constant interval
+    pub multiplier: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_millis(50),
+            max: Duration::from_millis(500),
+            multiplier: 2,
+        }
+    }
+}
+
+impl Backoff {
+    pub(crate) fn next(&self, interval: Duration) -> Duration {
+        (interval * self.multiplier.max(1)).min(self.max)
+    }
+}
+
+/// Dedicated electrum connection subscribed to `blockchain.headers.subscribe`, kept open by
+/// [ElectrsD] and reused by the `wait_height*` methods.
+///
+/// [RawClient] can't be used: it reads the socket only while waiting for the response of a
+/// request and queues the notifications received meanwhile, so it can't block until electrs
+/// pushes a new header. Messages are read directly from the socket instead, so waiting for a new
+/// tip blocks until electrs pushes the notification instead of polling.
+pub(crate) struct HeaderSubscription {
+    reader: BufReader<TcpStream>,
+    /// Bytes of the line being read, kept across read timeouts
+    line: Vec<u8>,
+    /// Id of the next request
+    next_id: u64,
+}
+
+impl HeaderSubscription {
+    fn connect(addr: SocketAddr) -> Result<Self, Error> {
+        Ok(HeaderSubscription {
+            reader: BufReader::new(TcpStream::connect(addr)?),
+            line: vec![],
+            next_id: 0,
+        })
+    }
+
+    /// Local address of the subscription connection
+    #[cfg(test)]
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.reader.get_ref().local_addr()
+    }
+
+    /// Wait until electrs notifies a tip at least at `height`, returns `false` if `deadline` is
+    /// reached before
+    fn wait_height(&mut self, height: usize, deadline: Instant) -> Result<bool, Error> {
+        let mut tip = match self.subscribe(deadline)? {
+            Some(tip) => tip,
+            None => return Ok(false),
+        };
+        while tip < height {
+            match self.next_message(deadline)? {
+                Some(message) => {
+                    if let Some(height) = notified_height(&message) {
+                        tip = height;
+                    }
+                }
+                None => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    /// (Re)subscribe, returning the current tip height once the response is received.
+    ///
+    /// Notifications pushed since the last call are skipped while waiting for the response.
+    fn subscribe(&mut self, deadline: Instant) -> Result<Option<usize>, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = format!(
+            "{{\"jsonrpc\":\"2.0\",\"id\":{},\"method\":\"blockchain.headers.subscribe\",\"params\":[]}}\n",
+            id
+        );
+        self.reader.get_mut().write_all(request.as_bytes())?;
+        loop {
+            let message = match self.next_message(deadline)? {
+                Some(message) => message,
+                None => return Ok(None),
+            };
+            if message.get("id").and_then(Value::as_u64) != Some(id) {
+                continue;
+            }
+            return match message
+                .get("result")
+                .and_then(|h| h.get("height"))
+                .and_then(Value::as_u64)
+            {
+                Some(height) => Ok(Some(height as usize)),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected subscription response: {}", message),
+                )
+                .into()),
+            };
+        }
+    }
+
+    /// Block until the next message, returns `None` if `deadline` is reached before
+    fn next_message(&mut self, deadline: Instant) -> Result<Option<Value>, Error> {
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            self.reader
+                .get_ref()
+                .set_read_timeout(Some(deadline - now))?;
+            // bytes read before a timeout stay in `self.line`, completed by the next call
+            match self.reader.read_until(b'\n', &mut self.line) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(_) if self.line.ends_with(b"\n") => (),
+                Ok(_) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e.into()),
+            }
+            let line = std::mem::take(&mut self.line);
+            let message = serde_json::from_slice(&line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            return Ok(Some(message));
+        }
+    }
+}
+
+/// Height of the header carried by a `blockchain.headers.subscribe` notification
+fn notified_height(message: &Value) -> Option<usize> {
+    let header = message.get("params").and_then(|p| p.get(0))?;
+    header
+        .get("height")
+        .and_then(Value::as_u64)
+        .map(|h| h as usize)
+}
 
 impl ElectrsD {
-    /// wait up to a minute the electrum server has indexed up to the given height.
-    pub fn wait_height(&self, height: usize) {
-        for _ in 0..600 {
-            match self.client.block_header_raw(height) {
-                Ok(_) => break,
-                Err(_) => thread::sleep(Duration::from_millis(100)),
+    /// Call `f` following the configured [Backoff] until it returns `Some` or an error, or
+    /// until `timeout` elapsed returning [Error::Timeout]
+    pub(crate) fn poll_until<T, F>(
+        &self,
+        what: &str,
+        timeout: Duration,
+        mut f: F,
+    ) -> Result<T, Error>
+    where
+        F: FnMut() -> Result<Option<T>, Error>,
+    {
+        let start = Instant::now();
+        let mut interval = self.backoff.initial;
+        loop {
+            if let Some(result) = f()? {
+                return Ok(result);
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Err(self.timeout_error(what.to_string(), elapsed));
             }
+            thread::sleep(interval.min(timeout - elapsed));
+            interval = self.backoff.next(interval);
         }
     }
 
-    /// wait up to a minute the electrum server has indexed the given transaction
+    /// wait up to [crate::Conf::wait_timeout] the electrum server has indexed up to the given height.
+    ///
+    /// Doesn't report failures, prefer [ElectrsD::wait_height_timeout].
+    pub fn wait_height(&self, height: usize) {
+        if let Err(e) = self.wait_height_timeout(height, self.wait_timeout) {
+            warn!("{}", e);
+        }
+    }
+
+    /// wait up to `timeout` the electrum server has indexed up to the given height.
+    ///
+    /// Header notifications pushed by electrs are awaited on a dedicated connection, polling is
+    /// used only if the subscription fails.
+    pub fn wait_height_timeout(&self, height: usize, timeout: Duration) -> Result<(), Error> {
+        let what = format!("height {}", height);
+        let start = Instant::now();
+        match self.wait_height_subscribed(height, start + timeout) {
+            Ok(true) => return Ok(()),
+            Ok(false) => return Err(self.timeout_error(what, start.elapsed())),
+            Err(e) => debug!("headers subscription failed, polling instead: {}", e),
+        }
+        self.poll_until(&what, timeout.saturating_sub(start.elapsed()), || {
+            Ok(self.client.block_header_raw(height).ok().map(|_| ()))
+        })
+    }
+
+    /// Returns `true` as soon as electrs notifies a tip at least at `height`, `false` if
+    /// `deadline` is reached before
+    fn wait_height_subscribed(&self, height: usize, deadline: Instant) -> Result<bool, Error> {
+        let mut subscription = self
+            .header_subscription
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if subscription.is_none() {
+            *subscription = Some(HeaderSubscription::connect(self.electrum_addr)?);
+        }
+        let result = subscription
+            .as_mut()
+            .expect("just connected")
+            .wait_height(height, deadline);
+        if result.is_err() {
+            // the connection is in an unknown state, a new one is opened by the next call
+            *subscription = None;
+        }
+        result
+    }
+
+    /// wait up to [crate::Conf::wait_timeout] the electrum server has indexed the given transaction
+    ///
+    /// Doesn't report failures, prefer [ElectrsD::wait_tx_timeout].
     pub fn wait_tx(&self, txid: &MalFixTxid) {
-        'main_loop: for _ in 0..600 {
-            match self.client.transaction_get(txid) {
-                Ok(tx) => {
-                    // having the raw tx doesn't mean the scripts has been indexed
-                    let txid = tx.malfix_txid();
-                    if let Some(output) = tx.output.first() {
-                        let history = self
-                            .client
-                            .script_get_history(&output.script_pubkey)
-                            .unwrap();
-                        for el in history {
-                            if el.tx_hash == txid {
-                                // the tx has to be updated atomically, so founding one is enough
-                                return;
-                            }
-                        }
-                        // the tx output has not been yet found
-                        continue 'main_loop;
-                    }
-                    // the tx has 0 ouptut, no need to ensure script_pubkey are indexed
-                    return;
-                }
-                Err(_) => thread::sleep(Duration::from_millis(100)),
+        if let Err(e) = self.wait_tx_timeout(txid, self.wait_timeout) {
+            warn!("{}", e);
+        }
+    }
+
+    /// wait up to `timeout` the electrum server has fully indexed the given transaction, meaning
+    /// it's in the history of every spendable output script and of the scripts it spends from
+    pub fn wait_tx_timeout(&self, txid: &MalFixTxid, timeout: Duration) -> Result<(), Error> {
+        self.poll_until(&format!("transaction {}", txid), timeout, || {
+            Ok(tx_history(&self.client, txid)?.map(|_| ()))
+        })
+    }
+
+    /// wait up to `timeout` the given transaction is confirmed with at least `min_confirmations`.
+    ///
+    /// Confirmations are computed from the height of the transaction in the history of its
+    /// scripts and the tip reported by electrs, so `min_confirmations = 1` means the
+    /// transaction is included in the last indexed block.
+    pub fn wait_tx_confirmed(
+        &self,
+        txid: &MalFixTxid,
+        min_confirmations: usize,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        // dedicated client, so that headers notifications of `self.client` are untouched
+        let client = RawClient::new(self.electrum_addr, None)?;
+        let what = format!(
+            "transaction {} with {} confirmations",
+            txid, min_confirmations
+        );
+        self.poll_until(&what, timeout, || {
+            let entries = match tx_history(&client, txid)? {
+                Some(entries) => entries,
+                None => return Ok(None),
+            };
+            if min_confirmations == 0 {
+                return Ok(Some(()));
+            }
+            let tip = client.block_headers_subscribe()?.height;
+            let confirmed = entries
+                .iter()
+                .all(|el| el.height > 0 && tip + 1 >= el.height as usize + min_confirmations);
+            Ok(if confirmed && !entries.is_empty() {
+                Some(())
+            } else {
+                None
+            })
+        })
+    }
+
+    /// mine `n` blocks with the given tapyrusd and wait up to [crate::Conf::wait_timeout] electrs
+    /// has indexed them, returns the hashes of the mined blocks.
+    ///
+    /// Blocks are signed with the Tapyrus aggregate private key and mined to `address`, or to a
+    /// new address of the tapyrusd wallet if `None`.
+    pub fn mine_blocks(
+        &self,
+        tapyrusd: &TapyrusD,
+        n: u64,
+        address: Option<&Address>,
+    ) -> anyhow::Result<Vec<BlockHash>> {
+        let new_address;
+        let address = match address {
+            Some(address) => address,
+            None => {
+                new_address = tapyrusd.client.get_new_address(None)?.assume_checked();
+                &new_address
+            }
+        };
+        let hashes = tapyrusd
+            .client
+            .generate_to_address(n, address, get_private_key())?;
+        let height = tapyrusd.client.get_block_count()?;
+        self.trigger()?;
+        self.wait_height_timeout(height as usize, self.wait_timeout)?;
+        Ok(hashes)
+    }
+
+    /// wait up to `timeout` the history of `script` satisfies `predicate`, returns the history.
+    ///
+    /// A dedicated connection subscribes to the script status, so the history is fetched again
+    /// only when electrs reports a status change.
+    pub fn wait_script_history<F>(
+        &self,
+        script: &Script,
+        mut predicate: F,
+        timeout: Duration,
+    ) -> Result<Vec<GetHistoryRes>, Error>
+    where
+        F: FnMut(&[GetHistoryRes]) -> bool,
+    {
+        self.wait_script(script, timeout, "history", |client| {
+            let history = client.script_get_history(script)?;
+            Ok(if predicate(&history) {
+                Some(history)
+            } else {
+                None
+            })
+        })
+    }
+
+    /// wait up to `timeout` the history of `script` has at least `len` entries, returns the history.
+    pub fn wait_script_history_len(
+        &self,
+        script: &Script,
+        len: usize,
+        timeout: Duration,
+    ) -> Result<Vec<GetHistoryRes>, Error> {
+        self.wait_script_history(script, |history| history.len() >= len, timeout)
+    }
+
+    /// wait up to `timeout` the balance of `script` satisfies `predicate`, returns the balance.
+    pub fn wait_script_balance<F>(
+        &self,
+        script: &Script,
+        mut predicate: F,
+        timeout: Duration,
+    ) -> Result<GetBalanceRes, Error>
+    where
+        F: FnMut(&GetBalanceRes) -> bool,
+    {
+        self.wait_script(script, timeout, "balance", |client| {
+            let balance = client.script_get_balance(script)?;
+            Ok(if predicate(&balance) {
+                Some(balance)
+            } else {
+                None
+            })
+        })
+    }
+
+    /// Subscribe `script` on a dedicated connection and call `check` at the beginning and at
+    /// every status change, until it returns `Some` or `timeout` elapsed
+    fn wait_script<T, F>(
+        &self,
+        script: &Script,
+        timeout: Duration,
+        what: &str,
+        mut check: F,
+    ) -> Result<T, Error>
+    where
+        F: FnMut(&RawClient<ElectrumPlaintextStream>) -> Result<Option<T>, Error>,
+    {
+        let client = RawClient::new(self.electrum_addr, None)?;
+        client.script_subscribe(script)?;
+        let mut changed = true;
+        self.poll_until(&format!("{} of script {:?}", what, script), timeout, || {
+            // status notifications are queued when the client reads from the socket
+            client.ping()?;
+            while client.script_pop(script)?.is_some() {
+                changed = true;
+            }
+            if !changed {
+                return Ok(None);
             }
+            changed = false;
+            check(&client)
+        })
+    }
+}
+
+/// Returns the history entries of the transaction `txid` in every script it touches: all
+/// its outputs scripts except the `OP_RETURN` ones and the scripts of the outputs it spends.
+///
+/// Returns `None` if the transaction, or any of the entries, is not yet indexed.
+pub(crate) fn tx_history(
+    client: &RawClient<ElectrumPlaintextStream>,
+    txid: &MalFixTxid,
+) -> Result<Option<Vec<GetHistoryRes>>, Error> {
+    // before being indexed the transaction is unknown to electrs
+    let tx = match unindexed_as_none(client.transaction_get(txid))? {
+        Some(tx) => tx,
+        None => return Ok(None),
+    };
+    let txid = tx.malfix_txid();
+    let mut entries = vec![];
+
+    for output in tx.output.iter() {
+        // electrs doesn't index the outputs that can't be spent, like OP_RETURN ones
+        if output.script_pubkey.is_provably_unspendable() {
+            continue;
         }
+        match history_entry(client, &output.script_pubkey, &txid)? {
+            Some(entry) => entries.push(entry),
+            None => return Ok(None),
+        }
+    }
+
+    for input in tx.input.iter() {
+        let previous_output = &input.previous_output;
+        if previous_output.is_null() {
+            // coinbase input
+            continue;
+        }
+        let previous_tx = match unindexed_as_none(client.transaction_get(&previous_output.txid))? {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+        let spent = match previous_tx.output.get(previous_output.vout as usize) {
+            Some(spent) => spent,
+            None => return Ok(None),
+        };
+        match history_entry(client, &spent.script_pubkey, &txid)? {
+            Some(entry) => entries.push(entry),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(entries))
+}
+
+/// Map the electrum protocol errors to `None`: electrs answers them while the transaction is not
+/// indexed yet, with a message depending on the version. Other errors, like I/O ones, are
+/// propagated
+fn unindexed_as_none<T>(result: Result<T, electrum_client::Error>) -> Result<Option<T>, Error> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(electrum_client::Error::Protocol(message)) => {
+            debug!("transaction not indexed yet: {}", message);
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
     }
 }
 
+fn history_entry(
+    client: &RawClient<ElectrumPlaintextStream>,
+    script: &Script,
+    txid: &MalFixTxid,
+) -> Result<Option<GetHistoryRes>, Error> {
+    let history = client.script_get_history(script)?;
+    Ok(history.into_iter().find(|el| el.tx_hash == *txid))
+}
+
 #[cfg(test)]
 mod test {
-    use crate::test::setup_nodes;
-    use electrum_client::{tapyrus::Amount, ElectrumApi};
+    use crate::test::{send_to, send_to_new_address, setup_nodes};
+    use crate::Error;
+    use electrum_client::tapyrus::{Amount, MalFixTxid};
+    use electrum_client::ElectrumApi;
+    use std::time::Duration;
     use tapyrusd::get_private_key;
+    use tapyrusd::tapyruscore_rpc::jsonrpc::serde_json::{self, json, Value};
     use tapyrusd::tapyruscore_rpc::RpcApi;
 
     #[test]
@@ -74,6 +513,42 @@ mod test {
         assert_eq!(header.height, 101);
     }
 
+    #[test]
+    fn test_wait_height_reuses_subscription() {
+        let (_, tapyrusd, electrsd) = setup_nodes();
+        let subscription_addr = || {
+            let subscription = electrsd.header_subscription.lock().unwrap();
+            subscription.as_ref().unwrap().local_addr().unwrap()
+        };
+        electrsd
+            .wait_height_timeout(1, Duration::from_secs(60))
+            .unwrap();
+        let addr = subscription_addr();
+
+        electrsd.mine_blocks(&tapyrusd, 2, None).unwrap();
+        electrsd
+            .wait_height_timeout(3, Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(subscription_addr(), addr);
+    }
+
+    #[test]
+    fn test_wait_height_timeout() {
+        let (_, tapyrusd, electrsd) = setup_nodes();
+        electrsd.mine_blocks(&tapyrusd, 10, None).unwrap();
+        electrsd
+            .wait_height_timeout(11, Duration::from_secs(60))
+            .unwrap();
+
+        let err = electrsd
+            .wait_height_timeout(1000, Duration::from_secs(1))
+            .unwrap_err();
+        match err {
+            Error::Timeout { elapsed, .. } => assert!(elapsed >= Duration::from_secs(1)),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
     #[test]
     fn test_wait_tx() {
         let (_, tapyrusd, electrsd) = setup_nodes();
@@ -115,4 +590,155 @@ mod test {
             .unwrap();
         assert_eq!(history.len(), 1);
     }
+
+    #[test]
+    fn test_wait_tx_timeout() {
+        let (_, tapyrusd, electrsd) = setup_nodes();
+        electrsd.mine_blocks(&tapyrusd, 100, None).unwrap();
+
+        let (address, txid) = send_to_new_address(&tapyrusd, Amount::from_tap(10000));
+        electrsd
+            .wait_tx_timeout(&txid, Duration::from_secs(60))
+            .unwrap();
+        let history = electrsd
+            .client
+            .script_get_history(&address.script_pubkey())
+            .unwrap();
+        assert_eq!(history.len(), 1);
+    }
+
+    #[test]
+    fn test_wait_tx_unknown() {
+        let (_, _tapyrusd, electrsd) = setup_nodes();
+        let txid: MalFixTxid = "0101010101010101010101010101010101010101010101010101010101010101"
+            .parse()
+            .unwrap();
+        let err = electrsd
+            .wait_tx_timeout(&txid, Duration::from_secs(1))
+            .unwrap_err();
+        match err {
+            Error::Timeout { elapsed, .. } => assert!(elapsed >= Duration::from_secs(1)),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_wait_tx_op_return_first() {
+        let (_, tapyrusd, electrsd) = setup_nodes();
+        electrsd.mine_blocks(&tapyrusd, 100, None).unwrap();
+
+        let address = tapyrusd
+            .client
+            .get_new_address(None)
+            .unwrap()
+            .assume_checked();
+        let mut payment = serde_json::Map::new();
+        payment.insert(address.to_string(), json!(0.0001));
+        let outputs = json!([{ "data": "74617079727573" }, payment]);
+        let raw: String = tapyrusd
+            .client
+            .call("createrawtransaction", &[json!([]), outputs])
+            .unwrap();
+        // change after the payment, so that the OP_RETURN output stays first
+        let funded: Value = tapyrusd
+            .client
+            .call(
+                "fundrawtransaction",
+                &[json!(raw), json!({ "changePosition": 2 })],
+            )
+            .unwrap();
+        let signed: Value = tapyrusd
+            .client
+            .call("signrawtransactionwithwallet", &[funded["hex"].clone()])
+            .unwrap();
+        let txid: String = tapyrusd
+            .client
+            .call("sendrawtransaction", &[signed["hex"].clone()])
+            .unwrap();
+        let txid: MalFixTxid = txid.parse().unwrap();
+
+        electrsd
+            .wait_tx_timeout(&txid, Duration::from_secs(60))
+            .unwrap();
+        let tx = electrsd.client.transaction_get(&txid).unwrap();
+        assert!(tx.output[0].script_pubkey.is_op_return());
+    }
+
+    #[test]
+    fn test_wait_script_history() {
+        let (_, tapyrusd, electrsd) = setup_nodes();
+        let generate_address = tapyrusd
+            .client
+            .get_new_address(None)
+            .unwrap()
+            .assume_checked();
+        tapyrusd
+            .client
+            .generate_to_address(100, &generate_address, get_private_key())
+            .unwrap();
+
+        let address = tapyrusd
+            .client
+            .get_new_address(None)
+            .unwrap()
+            .assume_checked();
+        let script = address.script_pubkey();
+        let history = electrsd
+            .wait_script_history_len(&script, 0, Duration::from_secs(60))
+            .unwrap();
+        assert!(history.is_empty());
+
+        let txid = send_to(&tapyrusd, &address, Amount::from_tap(10000));
+
+        let history = electrsd
+            .wait_script_history_len(&script, 1, Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(history[0].tx_hash, txid);
+        let balance = electrsd
+            .wait_script_balance(&script, |b| b.unconfirmed == 10000, Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(balance.confirmed, 0);
+    }
+
+    #[test]
+    fn test_wait_tx_confirmed() {
+        let (_, tapyrusd, electrsd) = setup_nodes();
+        let generate_address = tapyrusd
+            .client
+            .get_new_address(None)
+            .unwrap()
+            .assume_checked();
+        tapyrusd
+            .client
+            .generate_to_address(100, &generate_address, get_private_key())
+            .unwrap();
+
+        let (_, txid) = send_to_new_address(&tapyrusd, Amount::from_tap(10000));
+        assert!(electrsd
+            .wait_tx_confirmed(&txid, 1, Duration::from_secs(1))
+            .is_err());
+
+        tapyrusd
+            .client
+            .generate_to_address(2, &generate_address, get_private_key())
+            .unwrap();
+        electrsd.trigger().unwrap();
+        electrsd
+            .wait_tx_confirmed(&txid, 2, Duration::from_secs(60))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_mine_blocks() {
+        let (_, tapyrusd, electrsd) = setup_nodes();
+        let address = tapyrusd
+            .client
+            .get_new_address(None)
+            .unwrap()
+            .assume_checked();
+        let hashes = electrsd.mine_blocks(&tapyrusd, 10, Some(&address)).unwrap();
+        assert_eq!(hashes.len(), 10);
+        let header = electrsd.client.block_headers_subscribe().unwrap();
+        assert_eq!(header.height, 11);
+    }
 }
@@ -0,0 +1,31 @@
+//! Minimal HTTP client, used to probe and scrape the electrs endpoints without dependencies
+//!
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+/// Make a `GET` request of `path` to the server at `addr`, returns the status code and the body
+pub(crate) fn get(addr: &SocketAddr, path: &str, timeout: Duration) -> io::Result<(u16, String)> {
+    let mut stream = TcpStream::connect_timeout(addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    // HTTP/1.0 so that the server closes the connection and the body is not chunked
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, addr
+    )?;
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid http response");
+    let (head, body) = response.split_once("\r\n\r\n").ok_or_else(invalid)?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(invalid)?;
+    Ok((status, body.to_string()))
+}
@@ -6,17 +6,34 @@
 //! Utility to run a regtest electrsd process, useful in integration testing environment
 //!
 
+#[cfg(feature = "async")]
+mod async_electrsd;
+mod builder;
+#[cfg(test)]
+mod checksum;
 mod error;
+#[cfg(feature = "esplora")]
+pub mod esplora;
 mod ext;
+mod http;
+mod logs;
+mod metrics;
+mod proxy;
+#[cfg(feature = "tls")]
+mod tls;
+// shared with `build.rs`, which is the only user of some of the items
+#[allow(dead_code)]
 mod versions;
 
 use electrum_client::raw_client::{ElectrumPlaintextStream, RawClient};
 use log::{error, warn};
 use std::env;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
 use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
-use std::time::Duration;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tapyrusd::anyhow;
 use tapyrusd::anyhow::Context;
 use tapyrusd::tapyruscore_rpc::jsonrpc::serde_json::Value;
@@ -24,12 +41,21 @@ use tapyrusd::tapyruscore_rpc::RpcApi;
 use tapyrusd::tempfile::TempDir;
 use tapyrusd::{get_available_port, get_private_key, TapyrusD};
 
+use logs::LogBuffer;
+
 // re-export tapyrusd
 pub use tapyrusd;
 // re-export electrum_client because calling RawClient methods requires the ElectrumApi trait
 pub use electrum_client;
 
+#[cfg(all(feature = "async", feature = "esplora"))]
+pub use async_electrsd::AsyncEsploraClient;
+#[cfg(feature = "async")]
+pub use async_electrsd::{AsyncClient, AsyncElectrsD};
+pub use builder::ConfBuilder;
 pub use error::Error;
+pub use ext::Backoff;
+pub use proxy::Proxy;
 pub use which;
 
 /// Electrs configuration parameters, implements a convenient [Default] for most common use.
@@ -38,27 +64,68 @@ pub use which;
 /// ```
 /// let mut conf = electrsd::Conf::default();
 /// conf.view_stderr = false;
+/// conf.logs_capacity = 1000;
 /// conf.http_enabled = false;
-/// conf.network = "dev";
+/// conf.bind_addr = std::net::Ipv4Addr::LOCALHOST.into();
+/// conf.monitoring_enabled = true;
+/// conf.proxy_enabled = false;
+/// conf.daemon_proxy_enabled = false;
+/// conf.network = "dev".to_string();
 /// conf.tmpdir = None;
 /// conf.staticdir = None;
+/// conf.wait_timeout = std::time::Duration::from_secs(60);
+/// conf.backoff = electrsd::Backoff::default();
+/// conf.startup_timeout = std::time::Duration::from_secs(60);
+/// conf.shutdown_timeout = std::time::Duration::from_secs(10);
+/// conf.kill_on_parent_death = true;
 /// assert_eq!(conf, electrsd::Conf::default());
 /// ```
 #[derive(Debug, PartialEq, Eq, Clone)]
 #[non_exhaustive]
-pub struct Conf<'a> {
+pub struct Conf {
     /// Electrsd command line arguments
-    /// note that `db-dir`, `cookie`, `cookie-file`, `daemon-rpc-addr`, `jsonrpc-import`, `electrum-rpc-addr`, `monitoring-addr`, `http-addr`  cannot be used cause they are automatically initialized.
-    pub args: Vec<&'a str>,
+    /// note that `db-dir`, `network`, `cookie`, `cookie-file`, `daemon-rpc-addr`, `daemon-p2p-addr`, `jsonrpc-import`, `electrum-rpc-addr`, `monitoring-addr`, `http-addr`  cannot be used cause they are automatically initialized,
+    /// [ElectrsD::with_conf] returns [Error::ReservedArg] if any of them is found.
+    pub args: Vec<String>,
 
-    /// if `true` electrsd log output will not be suppressed
+    /// if `true` electrsd log output is printed on stderr, besides being captured in memory
     pub view_stderr: bool,
 
+    /// Maximum number of electrsd output lines kept in memory, see [ElectrsD::logs]
+    pub logs_capacity: usize,
+
     /// if `true` electrsd exposes an esplora endpoint
     pub http_enabled: bool,
 
+    /// Address the electrum, esplora and monitoring endpoints bind to.
+    ///
+    /// When unspecified (eg. `0.0.0.0`) endpoints listen on all interfaces, while the addresses
+    /// returned by [ElectrsD] use the loopback interface.
+    pub bind_addr: IpAddr,
+
+    /// if `true` the prometheus monitoring endpoint is reachable at [ElectrsD::monitoring_addr]
+    /// and probed at startup.
+    ///
+    /// electrs has no flag to disable it, when `false` it binds to a port chosen by the OS which
+    /// is never reported nor probed.
+    pub monitoring_enabled: bool,
+
+    /// if `true` a fault-injecting [Proxy] is put in front of the electrum endpoint, see
+    /// [ElectrsD::proxy]
+    pub proxy_enabled: bool,
+
+    /// if `true` electrs connects to the tapyrusd rpc and p2p ports through fault-injecting
+    /// [Proxy], see [ElectrsD::daemon_rpc_proxy]
+    pub daemon_proxy_enabled: bool,
+
+    /// if `true` a TLS terminator with a self-signed certificate generated at startup is put in
+    /// front of the electrum endpoint, see [ElectrsD::electrum_ssl_url]. Not supported by the
+    /// async variant, which returns [Error::UnsupportedOption].
+    #[cfg(feature = "tls")]
+    pub tls_enabled: bool,
+
     /// Must match tapyrusd network
-    pub network: &'a str,
+    pub network: String,
 
     /// Optionally specify a temporary or persistent working directory for the electrs.
     /// electrs index files will be stored in this path.
@@ -75,6 +142,30 @@ pub struct Conf<'a> {
     /// Persistent directory path
     pub staticdir: Option<PathBuf>,
 
+    /// Default timeout of the `wait_*` methods not taking an explicit one, like [ElectrsD::wait_height]
+    pub wait_timeout: Duration,
+
+    /// Polling strategy of the `wait_*` methods
+    pub backoff: Backoff,
+
+    /// Maximum time to wait for every enabled endpoint (electrum, esplora, monitoring) to accept
+    /// requests after the process is spawned, [Error::StartupTimeout] is returned otherwise.
+    ///
+    /// It is an overall deadline, shared by all the launch attempts.
+    pub startup_timeout: Duration,
+
+    /// Maximum time to wait for electrs to exit after `SIGINT` before killing it with `SIGKILL`,
+    /// used by [ElectrsD::kill], [ElectrsD::restart] and when [ElectrsD] is dropped. The async
+    /// variant uses it too, when dropped the wait happens in a background thread
+    pub shutdown_timeout: Duration,
+
+    /// if `true`, on Linux, electrs is killed when the test process exits, so that it is not left
+    /// running when the test process aborts or is killed. Ignored on other platforms.
+    ///
+    /// The signal is tied to the thread forking electrs, so electrs is always spawned by a
+    /// dedicated thread living as long as the process, whatever thread creates or restarts it.
+    pub kill_on_parent_death: bool,
+
     /// Try to spawn the process `attempt` time
     ///
     /// The OS is giving available ports to use, however, they aren't booked, so it could rarely
@@ -83,13 +174,20 @@ pub struct Conf<'a> {
     attempts: u8,
 }
 
-impl Default for Conf<'_> {
+impl Conf {
+    /// Returns a [ConfBuilder] initialized with the default values
+    pub fn builder() -> ConfBuilder {
+        ConfBuilder::new()
+    }
+}
+
+impl Default for Conf {
     fn default() -> Self {
         let args = if cfg!(feature = "electrs_0_5_1")
             || cfg!(feature = "electrs_0_5_0")
             || cfg!(feature = "legacy")
         {
-            vec!["-vvv"]
+            vec!["-vvv".to_string()]
         } else {
             vec![]
         };
@@ -97,10 +195,22 @@ impl Default for Conf<'_> {
         Conf {
             args,
             view_stderr: false,
+            logs_capacity: 1000,
             http_enabled: false,
-            network: "dev",
+            bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            monitoring_enabled: true,
+            proxy_enabled: false,
+            daemon_proxy_enabled: false,
+            #[cfg(feature = "tls")]
+            tls_enabled: false,
+            network: "dev".to_string(),
             tmpdir: None,
             staticdir: None,
+            wait_timeout: Duration::from_secs(60),
+            backoff: Backoff::default(),
+            startup_timeout: Duration::from_secs(60),
+            shutdown_timeout: Duration::from_secs(10),
+            kill_on_parent_death: true,
             attempts: 3,
         }
     }
@@ -112,12 +222,47 @@ pub struct ElectrsD {
     process: Child,
     /// Electrum client connected to the electrs process
     pub client: RawClient<ElectrumPlaintextStream>,
+    /// Executable and arguments used to spawn the process, kept to restart it
+    exe: OsString,
+    args: Vec<String>,
+    /// See [Conf::view_stderr], kept to restart the process
+    view_stderr: bool,
+    /// See [Conf::startup_timeout], kept to restart the process
+    startup_timeout: Duration,
+    /// See [Conf::shutdown_timeout]
+    shutdown_timeout: Duration,
+    /// See [Conf::kill_on_parent_death], kept to restart the process
+    kill_on_parent_death: bool,
     /// Work directory, where the electrs stores indexes and other stuffs.
     work_dir: DataDir,
-    /// Url to connect to the electrum protocol (tcp)
-    pub electrum_url: String,
-    /// Url to connect to esplora protocol (http)
-    pub esplora_url: Option<String>,
+    /// Address to connect to the electrum protocol (tcp)
+    electrum_addr: SocketAddr,
+    /// Address to connect to the esplora protocol (http)
+    esplora_addr: Option<SocketAddr>,
+    /// Address to scrape the prometheus monitoring endpoint (http)
+    monitoring_addr: Option<SocketAddr>,
+    /// Enabled endpoints, awaited to be ready when the process is restarted
+    endpoints: Vec<(Endpoint, SocketAddr)>,
+    /// Last lines of the electrs output
+    logs: Arc<LogBuffer>,
+    /// Default timeout of the `wait_*` methods
+    wait_timeout: Duration,
+    /// Polling strategy of the `wait_*` methods
+    backoff: Backoff,
+    /// Connection awaiting header notifications, opened by the first `wait_height*` call
+    header_subscription: Mutex<Option<ext::HeaderSubscription>>,
+    /// Fault-injecting proxy in front of the electrum endpoint
+    proxy: Option<Proxy>,
+    /// Fault-injecting proxy between electrs and the tapyrusd rpc port
+    daemon_rpc_proxy: Option<Proxy>,
+    /// Fault-injecting proxy between electrs and the tapyrusd p2p port
+    daemon_p2p_proxy: Option<Proxy>,
+    /// TLS terminator in front of the electrum endpoint
+    #[cfg(feature = "tls")]
+    tls: Option<tls::TlsProxy>,
+    /// Electrum client connected over TLS, `Some` if [Conf::tls_enabled] is set
+    #[cfg(feature = "tls")]
+    pub tls_client: Option<RawClient<electrum_client::raw_client::ElectrumSslStream>>,
 }
 
 /// The DataDir struct defining the kind of data directory electrs will use.
@@ -139,18 +284,54 @@ impl DataDir {
     }
 }
 
-impl ElectrsD {
-    /// Create a new electrs process connected with the given tapyrusd and default args.
-    pub fn new<S: AsRef<OsStr>>(exe: S, tapyrusd: &TapyrusD) -> anyhow::Result<ElectrsD> {
-        ElectrsD::with_conf(exe, tapyrusd, &Conf::default())
+/// The network endpoints exposed by electrs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    /// Electrum protocol (tcp)
+    Electrum,
+    /// Esplora protocol (http), enabled by [Conf::http_enabled]
+    Esplora,
+    /// Prometheus monitoring (http), enabled by [Conf::monitoring_enabled]
+    Monitoring,
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Electrum => write!(f, "electrum"),
+            Endpoint::Esplora => write!(f, "esplora"),
+            Endpoint::Monitoring => write!(f, "monitoring"),
+        }
     }
+}
 
-    /// Create a new electrs process using given [Conf] connected with the given tapyrusd
-    pub fn with_conf<S: AsRef<OsStr>>(
-        exe: S,
-        tapyrusd: &TapyrusD,
-        conf: &Conf,
-    ) -> anyhow::Result<ElectrsD> {
+impl Endpoint {
+    /// Returns `true` if the endpoint at `addr` accepts requests
+    pub(crate) fn is_ready(&self, addr: &SocketAddr) -> bool {
+        let timeout = Duration::from_secs(1);
+        match self {
+            Endpoint::Electrum => TcpStream::connect_timeout(addr, timeout).is_ok(),
+            // any http response, even an error, means the server is up
+            Endpoint::Esplora => http::get(addr, "/blocks/tip/height", timeout).is_ok(),
+            Endpoint::Monitoring => http::get(addr, "/", timeout).is_ok(),
+        }
+    }
+}
+
+/// Arguments and resources needed to spawn an electrs process
+pub(crate) struct Launch {
+    pub(crate) args: Vec<String>,
+    pub(crate) work_dir: DataDir,
+    pub(crate) electrum_addr: SocketAddr,
+    pub(crate) esplora_addr: Option<SocketAddr>,
+    pub(crate) monitoring_addr: Option<SocketAddr>,
+    pub(crate) daemon_rpc_proxy: Option<Proxy>,
+    pub(crate) daemon_p2p_proxy: Option<Proxy>,
+}
+
+impl Launch {
+    /// Prepare the work directory and the electrs arguments to connect to the given tapyrusd
+    pub(crate) fn new(tapyrusd: &TapyrusD, conf: &Conf) -> anyhow::Result<Launch> {
         let response = tapyrusd.client.call::<Value>("getblockchaininfo", &[])?;
         if response
             .get("initialblockdownload")
@@ -169,7 +350,8 @@ impl ElectrsD {
                 .unwrap();
         }
 
-        let mut args = conf.args.clone();
+        builder::check_args(&conf.args)?;
+        let mut args: Vec<&str> = conf.args.iter().map(String::as_str).collect();
 
         let work_dir = match (&conf.tmpdir, &conf.staticdir) {
             (Some(_), Some(_)) => return Err(Error::BothDirsSpecified.into()),
@@ -189,7 +371,7 @@ impl ElectrsD {
         args.push(&db_dir);
 
         args.push("--network");
-        args.push(conf.network);
+        args.push(&conf.network);
 
         #[cfg(not(feature = "legacy"))]
         let cookie_file;
@@ -212,84 +394,215 @@ impl ElectrsD {
             args.push(&cookie_value);
         }
 
+        let daemon_proxy = |target: SocketAddr| -> Result<(SocketAddr, Option<Proxy>), Error> {
+            if conf.daemon_proxy_enabled {
+                let proxy = Proxy::new(target)?;
+                Ok((proxy.addr(), Some(proxy)))
+            } else {
+                Ok((target, None))
+            }
+        };
+
         args.push("--daemon-rpc-addr");
-        let rpc_socket = tapyrusd.params.rpc_socket.to_string();
+        let (rpc_addr, daemon_rpc_proxy) = daemon_proxy(tapyrusd.params.rpc_socket.into())?;
+        let rpc_socket = rpc_addr.to_string();
         args.push(&rpc_socket);
 
         let p2p_socket;
-        if cfg!(feature = "electrs_0_5_0")
+        let daemon_p2p_proxy = if cfg!(feature = "electrs_0_5_0")
             || cfg!(feature = "electrs_0_5_1")
             || cfg!(feature = "legacy")
         {
             args.push("--jsonrpc-import");
+            None
         } else {
             args.push("--daemon-p2p-addr");
-            p2p_socket = tapyrusd
+            let p2p_addr = tapyrusd
                 .params
                 .p2p_socket
-                .expect("electrs_0_5_1 or electrs_0_5_0 requires tapyrusd with p2p port open")
-                .to_string();
+                .expect("electrs_0_5_1 or electrs_0_5_0 requires tapyrusd with p2p port open");
+            let (p2p_addr, daemon_p2p_proxy) = daemon_proxy(p2p_addr.into())?;
+            p2p_socket = p2p_addr.to_string();
             args.push(&p2p_socket);
-        }
+            daemon_p2p_proxy
+        };
 
-        let electrum_url = format!("0.0.0.0:{}", get_available_port()?);
+        let electrum_addr = SocketAddr::new(conf.bind_addr, get_available_port()?);
+        let electrum_bind = electrum_addr.to_string();
         args.push("--electrum-rpc-addr");
-        args.push(&electrum_url);
+        args.push(&electrum_bind);
 
-        // would be better to disable it, didn't found a flag
-        let monitoring = format!("0.0.0.0:{}", get_available_port()?);
+        // there is no flag to disable monitoring, when not enabled let the OS choose the port
+        let monitoring_port = if conf.monitoring_enabled {
+            get_available_port()?
+        } else {
+            0
+        };
+        let monitoring_addr = SocketAddr::new(conf.bind_addr, monitoring_port);
+        let monitoring_bind = monitoring_addr.to_string();
         args.push("--monitoring-addr");
-        args.push(&monitoring);
+        args.push(&monitoring_bind);
+        let monitoring_addr = Some(monitoring_addr).filter(|_| conf.monitoring_enabled);
 
-        let esplora_url_string;
-        let esplora_url = if conf.http_enabled {
-            esplora_url_string = format!("0.0.0.0:{}", get_available_port()?);
+        let esplora_bind;
+        let esplora_addr = if conf.http_enabled {
+            let esplora_addr = SocketAddr::new(conf.bind_addr, get_available_port()?);
+            esplora_bind = esplora_addr.to_string();
             args.push("--http-addr");
-            args.push(&esplora_url_string);
-            #[allow(clippy::redundant_clone)]
-            Some(esplora_url_string.clone())
+            args.push(&esplora_bind);
+            Some(esplora_addr)
         } else {
             None
         };
 
-        let view_stderr = if conf.view_stderr {
-            Stdio::inherit()
-        } else {
-            Stdio::null()
-        };
+        Ok(Launch {
+            args: args.into_iter().map(String::from).collect(),
+            work_dir,
+            electrum_addr: connectable(electrum_addr),
+            esplora_addr: esplora_addr.map(connectable),
+            monitoring_addr: monitoring_addr.map(connectable),
+            daemon_rpc_proxy,
+            daemon_p2p_proxy,
+        })
+    }
+
+    /// Endpoints to probe before considering electrs started, with their address
+    pub(crate) fn endpoints(&self) -> Vec<(Endpoint, SocketAddr)> {
+        let mut endpoints = vec![(Endpoint::Electrum, self.electrum_addr)];
+        if let Some(esplora_addr) = self.esplora_addr {
+            endpoints.push((Endpoint::Esplora, esplora_addr));
+        }
+        if let Some(monitoring_addr) = self.monitoring_addr {
+            endpoints.push((Endpoint::Monitoring, monitoring_addr));
+        }
+        endpoints
+    }
+}
+
+/// Returns the address to connect to a server bound to `addr`, replacing the unspecified ip
+/// with the loopback one
+fn connectable(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => (Ipv4Addr::LOCALHOST, addr.port()).into(),
+        IpAddr::V6(ip) if ip.is_unspecified() => (Ipv6Addr::LOCALHOST, addr.port()).into(),
+        _ => addr,
+    }
+}
+
+impl ElectrsD {
+    /// Create a new electrs process connected with the given tapyrusd and default args.
+    pub fn new<S: AsRef<OsStr>>(exe: S, tapyrusd: &TapyrusD) -> anyhow::Result<ElectrsD> {
+        ElectrsD::with_conf(exe, tapyrusd, &Conf::default())
+    }
+
+    /// Create a new electrs process using given [Conf] connected with the given tapyrusd
+    pub fn with_conf<S: AsRef<OsStr>>(
+        exe: S,
+        tapyrusd: &TapyrusD,
+        conf: &Conf,
+    ) -> anyhow::Result<ElectrsD> {
+        Self::with_conf_since(exe, tapyrusd, conf, Instant::now())
+    }
+
+    /// See [ElectrsD::with_conf], `start` is the instant of the first launch attempt
+    fn with_conf_since<S: AsRef<OsStr>>(
+        exe: S,
+        tapyrusd: &TapyrusD,
+        conf: &Conf,
+        start: Instant,
+    ) -> anyhow::Result<ElectrsD> {
+        let launch = Launch::new(tapyrusd, conf)?;
+        let endpoints = launch.endpoints();
+        let Launch {
+            args,
+            work_dir,
+            electrum_addr,
+            esplora_addr,
+            monitoring_addr,
+            daemon_rpc_proxy,
+            daemon_p2p_proxy,
+        } = launch;
 
         println!("args: {:?}", args);
-        let mut process = Command::new(&exe)
-            .args(args)
-            .stderr(view_stderr)
-            .spawn()
-            .with_context(|| format!("Error while executing {:?}", exe.as_ref()))?;
-
-        let client = loop {
-            if let Some(status) = process.try_wait()? {
-                if conf.attempts > 0 {
-                    warn!("early exit with: {:?}. Trying to launch again ({} attempts remaining), maybe some other process used our available port", status, conf.attempts);
-                    let mut conf = conf.clone();
-                    conf.attempts -= 1;
-                    return Self::with_conf(exe, tapyrusd, &conf)
-                        .with_context(|| format!("Remaining attempts {}", conf.attempts));
-                } else {
-                    error!("early exit with: {:?}", status);
-                    return Err(Error::EarlyExit(status).into());
-                }
-            }
-            match RawClient::new(&electrum_url, None) {
-                Ok(client) => break client,
-                Err(_) => std::thread::sleep(Duration::from_millis(500)),
+        let logs = LogBuffer::new(conf.logs_capacity);
+        let mut process = spawn(
+            exe.as_ref(),
+            &args,
+            &logs,
+            conf.view_stderr,
+            conf.kill_on_parent_death,
+        )?;
+
+        let ready = wait_ready(
+            &mut process,
+            endpoints.clone(),
+            &logs,
+            start,
+            conf.startup_timeout,
+        );
+        if let Some(status) = ready? {
+            if conf.attempts > 0 {
+                warn!("early exit with: {:?}. Trying to launch again ({} attempts remaining), maybe some other process used our available port", status, conf.attempts);
+                let mut conf = conf.clone();
+                conf.attempts -= 1;
+                return Self::with_conf_since(exe, tapyrusd, &conf, start)
+                    .with_context(|| format!("Remaining attempts {}", conf.attempts));
+            } else {
+                error!("early exit with: {:?}", status);
+                return Err(early_exit(status, &logs).into());
             }
+        }
+        // from now on a failure must not leave the ready electrs running
+        let client = or_kill(RawClient::new(electrum_addr, None), &mut process)?;
+        let proxy = if conf.proxy_enabled {
+            Some(or_kill(
+                Proxy::bind(electrum_addr, conf.bind_addr),
+                &mut process,
+            )?)
+        } else {
+            None
+        };
+
+        #[cfg(feature = "tls")]
+        let tls = if conf.tls_enabled {
+            Some(or_kill(
+                tls::TlsProxy::start(electrum_addr, conf.bind_addr, &work_dir.path()),
+                &mut process,
+            )?)
+        } else {
+            None
+        };
+        #[cfg(feature = "tls")]
+        let tls_client = match &tls {
+            Some(tls) => Some(or_kill(tls::connect(tls.addr()), &mut process)?),
+            None => None,
         };
 
         Ok(ElectrsD {
             process,
             client,
+            exe: exe.as_ref().to_owned(),
+            args,
+            view_stderr: conf.view_stderr,
+            startup_timeout: conf.startup_timeout,
+            shutdown_timeout: conf.shutdown_timeout,
+            kill_on_parent_death: conf.kill_on_parent_death,
             work_dir,
-            electrum_url,
-            esplora_url,
+            electrum_addr,
+            esplora_addr,
+            monitoring_addr,
+            endpoints,
+            logs,
+            wait_timeout: conf.wait_timeout,
+            backoff: conf.backoff,
+            header_subscription: Mutex::new(None),
+            proxy,
+            daemon_rpc_proxy,
+            daemon_p2p_proxy,
+            #[cfg(feature = "tls")]
+            tls,
+            #[cfg(feature = "tls")]
+            tls_client,
         })
     }
 
@@ -312,23 +625,106 @@ impl ElectrsD {
         self.work_dir.path()
     }
 
-    /// terminate the electrs process
+    /// Address to connect to the electrum protocol
+    pub fn electrum_addr(&self) -> SocketAddr {
+        self.electrum_addr
+    }
+
+    /// Url to connect to the electrum protocol, like `tcp://127.0.0.1:50001`
+    pub fn electrum_url(&self) -> String {
+        format!("tcp://{}", self.electrum_addr)
+    }
+
+    /// Address to connect to the esplora protocol, `None` if [Conf::http_enabled] is not set
+    pub fn esplora_addr(&self) -> Option<SocketAddr> {
+        self.esplora_addr
+    }
+
+    /// Base url of the esplora REST API, like `http://127.0.0.1:3002`, `None` if
+    /// [Conf::http_enabled] is not set
+    pub fn esplora_base_url(&self) -> Option<String> {
+        self.esplora_addr.map(|addr| format!("http://{}", addr))
+    }
+
+    /// Stop electrs gracefully and spawn it again with the same arguments, so that it reuses the
+    /// same work directory and ports, then reconnect [ElectrsD::client].
+    ///
+    /// With a persistent work directory electrs resumes from its on-disk index. Output lines of
+    /// both processes are kept in the same buffer, see [ElectrsD::logs]. Returns
+    /// [Error::EarlyExit] if the new process exits before being ready, for example because one of
+    /// the ports has been taken meanwhile.
+    pub fn restart(&mut self) -> anyhow::Result<()> {
+        self.shutdown(self.shutdown_timeout)?;
+
+        let mut process = spawn(
+            &self.exe,
+            &self.args,
+            &self.logs,
+            self.view_stderr,
+            self.kill_on_parent_death,
+        )?;
+        let ready = wait_ready(
+            &mut process,
+            self.endpoints.clone(),
+            &self.logs,
+            Instant::now(),
+            self.startup_timeout,
+        );
+        // the new process is the one to terminate from now on, even if it is not ready
+        self.process = process;
+        if let Some(status) = ready? {
+            error!("early exit after restart with: {:?}", status);
+            return Err(early_exit(status, &self.logs).into());
+        }
+
+        self.client = RawClient::new(self.electrum_addr, None)?;
+        *self
+            .header_subscription
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner()) = None;
+        #[cfg(feature = "tls")]
+        {
+            self.tls_client = match &self.tls {
+                Some(tls) => Some(tls::connect(tls.addr())?),
+                None => None,
+            };
+        }
+        Ok(())
+    }
+
+    /// terminate the electrs process, see [ElectrsD::shutdown] with [Conf::shutdown_timeout]
     pub fn kill(&mut self) -> anyhow::Result<()> {
-        match self.work_dir {
-            DataDir::Persistent(_) => {
-                self.inner_kill()?;
-                // Wait for the process to exit
-                match self.process.wait() {
-                    Ok(_) => Ok(()),
-                    Err(e) => Err(e.into()),
-                }
+        self.shutdown(self.shutdown_timeout)?;
+        Ok(())
+    }
+
+    /// Ask electrs to terminate with `SIGINT` and wait up to `timeout` for it to exit, then kill
+    /// it with `SIGKILL`. The process is always reaped and its exit status returned.
+    ///
+    /// Calling it again after the process exited returns the same status.
+    pub fn shutdown(&mut self, timeout: Duration) -> Result<ExitStatus, Error> {
+        if let Some(status) = self.process.try_wait()? {
+            return Ok(status);
+        }
+        self.inner_kill()?;
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if let Some(status) = self.process.try_wait()? {
+                return Ok(status);
             }
-            DataDir::Temporary(_) => Ok(self.process.kill()?),
+            std::thread::sleep(Duration::from_millis(50));
         }
+        warn!(
+            "electrs not terminated after {:?}, sending SIGKILL",
+            timeout
+        );
+        // the process may exit between the last check and the kill, which then fails
+        let _ = self.process.kill();
+        Ok(self.process.wait()?)
     }
 
     #[cfg(not(target_os = "windows"))]
-    fn inner_kill(&mut self) -> anyhow::Result<()> {
+    fn inner_kill(&mut self) -> Result<(), Error> {
         // Send SIGINT signal to electrsd
         Ok(nix::sys::signal::kill(
             nix::unistd::Pid::from_raw(self.process.id() as i32),
@@ -337,25 +733,182 @@ impl ElectrsD {
     }
 
     #[cfg(target_os = "windows")]
-    fn inner_kill(&mut self) -> anyhow::Result<()> {
+    fn inner_kill(&mut self) -> Result<(), Error> {
         Ok(self.process.kill()?)
     }
 }
 
+/// Spawn electrs capturing its output in `logs`
+fn spawn(
+    exe: &OsStr,
+    args: &[String],
+    logs: &Arc<LogBuffer>,
+    tee: bool,
+    kill_on_parent_death: bool,
+) -> anyhow::Result<Child> {
+    let mut command = Command::new(exe);
+    command
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(target_os = "linux")]
+    let spawned = if kill_on_parent_death {
+        use std::os::unix::process::CommandExt;
+        // SAFETY: the hook only calls async-signal-safe functions
+        unsafe {
+            command.pre_exec(parent_death_signal());
+        }
+        on_spawner_thread(move || command.spawn())
+    } else {
+        command.spawn()
+    };
+    #[cfg(not(target_os = "linux"))]
+    let spawned = {
+        let _ = kill_on_parent_death;
+        command.spawn()
+    };
+    let mut process = spawned.with_context(|| format!("Error while executing {:?}", exe))?;
+    if let Some(stdout) = process.stdout.take() {
+        logs.capture(stdout, tee);
+    }
+    if let Some(stderr) = process.stderr.take() {
+        logs.capture(stderr, tee);
+    }
+    Ok(process)
+}
+
+/// Job executed by the spawner thread, see [on_spawner_thread]
+#[cfg(target_os = "linux")]
+type SpawnerJob = Box<dyn FnOnce() + Send>;
+
+/// Channel to the spawner thread, started by the first [on_spawner_thread] call
+#[cfg(target_os = "linux")]
+static SPAWNER: Mutex<Option<std::sync::mpsc::Sender<SpawnerJob>>> = Mutex::new(None);
+
+/// Run `f` on a thread living as long as the process and return its result.
+///
+/// `PR_SET_PDEATHSIG` is delivered when the thread forking the child exits, not the process, so
+/// processes spawned with [parent_death_signal] must be forked by this thread to survive the
+/// exit of the thread creating or restarting them.
+#[cfg(target_os = "linux")]
+pub(crate) fn on_spawner_thread<T, F>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (result_sender, result_receiver) = std::sync::mpsc::channel();
+    let job: SpawnerJob = Box::new(move || {
+        let _ = result_sender.send(f());
+    });
+    {
+        let mut spawner = SPAWNER.lock().unwrap_or_else(|e| e.into_inner());
+        let sender = spawner.get_or_insert_with(|| {
+            let (sender, receiver) = std::sync::mpsc::channel::<SpawnerJob>();
+            std::thread::Builder::new()
+                .name("electrsd-spawner".to_string())
+                .spawn(move || receiver.into_iter().for_each(|job| job()))
+                .expect("failed to start the spawner thread");
+            sender
+        });
+        sender.send(job).expect("spawner thread exited");
+    }
+    result_receiver.recv().expect("spawner thread exited")
+}
+
+/// Hook to run in the child before exec, so that it receives `SIGKILL` when the forking thread
+/// exits, see [on_spawner_thread]. Fails if the parent already exited, as the signal would never
+/// be delivered.
+#[cfg(target_os = "linux")]
+pub(crate) fn parent_death_signal() -> impl FnMut() -> std::io::Result<()> + Send + Sync + 'static {
+    let parent = std::process::id() as nix::libc::pid_t;
+    move || {
+        // SAFETY: prctl and getppid are async-signal-safe
+        unsafe {
+            if nix::libc::prctl(nix::libc::PR_SET_PDEATHSIG, nix::libc::SIGKILL) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if nix::libc::getppid() != parent {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "parent exited before electrs started",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wait for every `pending` endpoint to accept requests, returns the exit status if electrs exits
+/// before. If `timeout` elapses since `start` the process is killed and [Error::StartupTimeout]
+/// is returned.
+fn wait_ready(
+    process: &mut Child,
+    mut pending: Vec<(Endpoint, SocketAddr)>,
+    logs: &LogBuffer,
+    start: Instant,
+    timeout: Duration,
+) -> Result<Option<ExitStatus>, Error> {
+    loop {
+        if let Some(status) = process.try_wait()? {
+            return Ok(Some(status));
+        }
+        pending.retain(|(endpoint, addr)| !endpoint.is_ready(addr));
+        if pending.is_empty() {
+            return Ok(None);
+        }
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            let (endpoint, addr) = pending.remove(0);
+            error!(
+                "{} endpoint at {} not ready after {:?}",
+                endpoint, addr, elapsed
+            );
+            let _ = process.kill();
+            let _ = process.wait();
+            return Err(Error::StartupTimeout {
+                endpoint,
+                addr,
+                elapsed,
+                logs: logs.tail(logs::ERROR_LOG_LINES),
+            });
+        }
+        std::thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// Kill and reap `process` if `result` is an error, so that electrs is not left running when
+/// [ElectrsD::with_conf] fails after spawning it
+fn or_kill<T, E>(result: Result<T, E>, process: &mut Child) -> Result<T, E> {
+    if result.is_err() {
+        let _ = process.kill();
+        let _ = process.wait();
+    }
+    result
+}
+
+/// Build an [Error::EarlyExit] waiting for the last output lines of the exited process
+fn early_exit(status: ExitStatus, logs: &LogBuffer) -> Error {
+    logs.wait_closed(Duration::from_secs(1));
+    Error::EarlyExit {
+        status,
+        logs: logs.tail(logs::ERROR_LOG_LINES),
+    }
+}
+
 impl Drop for ElectrsD {
     fn drop(&mut self) {
-        let _ = self.kill();
+        if let Err(e) = self.shutdown(self.shutdown_timeout) {
+            error!("failed to shutdown electrs: {}", e);
+        }
     }
 }
 
 /// Provide the electrs executable path if a version feature has been specified and `ELECTRSD_SKIP_DOWNLOAD` is not set.
+///
+/// The executable lives in the cache shared across builds, see the `ELECTRSD_CACHE_DIR` env var.
 pub fn downloaded_exe_path() -> Option<String> {
     if versions::HAS_FEATURE && std::env::var_os("ELECTRSD_SKIP_DOWNLOAD").is_none() {
-        Some(format!(
-            "{}/electrs/{}/electrs",
-            env!("OUT_DIR"),
-            versions::electrs_name(),
-        ))
+        option_env!("ELECTRSD_DOWNLOADED_EXE").map(String::from)
     } else {
         None
     }
@@ -396,6 +949,9 @@ mod test {
     use electrum_client::ElectrumApi;
     use log::{debug, log_enabled, Level};
     use std::env;
+    use std::ffi::OsStr;
+    use std::time::Duration;
+    use tapyrusd::tapyruscore_rpc::tapyrus::{Address, Amount, MalFixTxid};
     use tapyrusd::tapyruscore_rpc::RpcApi;
 
     #[test]
@@ -414,6 +970,12 @@ mod test {
         let (electrs_exe, tapyrusd, electrsd) = setup_nodes();
         let header = electrsd.client.block_headers_subscribe().unwrap();
         assert_eq!(header.height, 1);
+        assert!(electrsd.electrum_addr().ip().is_loopback());
+        assert_eq!(
+            electrsd.electrum_url(),
+            format!("tcp://{}", electrsd.electrum_addr())
+        );
+        assert_eq!(electrsd.esplora_base_url(), None);
         let address = tapyrusd
             .client
             .get_new_address(None)
@@ -441,6 +1003,122 @@ mod test {
         assert_eq!(header.height, 101);
     }
 
+    #[test]
+    fn test_connectable() {
+        let addr: std::net::SocketAddr = "0.0.0.0:3002".parse().unwrap();
+        assert_eq!(super::connectable(addr).to_string(), "127.0.0.1:3002");
+        let addr: std::net::SocketAddr = "[::]:3002".parse().unwrap();
+        assert_eq!(super::connectable(addr).to_string(), "[::1]:3002");
+        let addr: std::net::SocketAddr = "192.168.1.2:3002".parse().unwrap();
+        assert_eq!(super::connectable(addr), addr);
+    }
+
+    #[test]
+    fn test_restart() {
+        let (electrs_exe, tapyrusd) = setup_tapyrusd();
+        let staticdir = tapyrusd::tempfile::tempdir().unwrap();
+        let conf = crate::Conf {
+            staticdir: Some(staticdir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let mut electrsd = ElectrsD::with_conf(&electrs_exe, &tapyrusd, &conf).unwrap();
+        electrsd.mine_blocks(&tapyrusd, 10, None).unwrap();
+        let electrum_url = electrsd.electrum_url();
+
+        electrsd.restart().unwrap();
+        assert_eq!(electrsd.electrum_url(), electrum_url);
+        assert_eq!(electrsd.workdir(), staticdir.path());
+        let header = electrsd.client.block_headers_subscribe().unwrap();
+        assert_eq!(header.height, 11);
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let (_, _tapyrusd, mut electrsd) = setup_nodes();
+        let status = electrsd.shutdown(Duration::from_secs(10)).unwrap();
+        assert!(electrsd.client.ping().is_err());
+        // already reaped, the same status is returned
+        assert_eq!(electrsd.shutdown(Duration::from_secs(10)).unwrap(), status);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_spawning_thread_exit() {
+        let (electrs_exe, tapyrusd) = setup_tapyrusd();
+        let mut electrsd = std::thread::scope(|s| {
+            s.spawn(|| ElectrsD::new(&electrs_exe, &tapyrusd).unwrap())
+                .join()
+                .unwrap()
+        });
+        // electrs survives the thread spawning it
+        std::thread::sleep(Duration::from_millis(500));
+        electrsd.client.ping().unwrap();
+
+        std::thread::scope(|s| s.spawn(|| electrsd.restart().unwrap()).join().unwrap());
+        std::thread::sleep(Duration::from_millis(500));
+        electrsd.client.ping().unwrap();
+    }
+
+    /// Set when the test binary is re-executed by [test_kill_on_parent_death]
+    #[cfg(target_os = "linux")]
+    const PARENT_DEATH_CHILD: &str = "ELECTRSD_TEST_PARENT_DEATH_CHILD";
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_kill_on_parent_death() {
+        use nix::sys::signal::{raise, Signal};
+        use std::os::unix::process::ExitStatusExt;
+
+        if env::var_os(PARENT_DEATH_CHILD).is_some() {
+            // re-executed: spawn from a short-lived thread, then get killed without cleanup
+            let child = std::thread::spawn(|| {
+                let logs = crate::logs::LogBuffer::new(10);
+                crate::spawn(
+                    OsStr::new("sleep"),
+                    &["1000".to_string()],
+                    &logs,
+                    false,
+                    true,
+                )
+                .unwrap()
+            })
+            .join()
+            .unwrap();
+            println!("child pid {}", child.id());
+            raise(Signal::SIGKILL).unwrap();
+        }
+
+        let output = std::process::Command::new(env::current_exe().unwrap())
+            .args(&["test::test_kill_on_parent_death", "--exact", "--nocapture"])
+            .env(PARENT_DEATH_CHILD, "1")
+            .output()
+            .unwrap();
+        assert_eq!(output.status.signal(), Some(Signal::SIGKILL as i32));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let pid = stdout
+            .lines()
+            .find_map(|line| line.strip_prefix("child pid "))
+            .unwrap()
+            .trim()
+            .to_string();
+
+        // the orphan may stay a zombie if nobody reaps it, it is dead anyway
+        let is_running = || match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat) => !stat
+                .rsplit(')')
+                .next()
+                .unwrap_or_default()
+                .trim_start()
+                .starts_with('Z'),
+            Err(_) => false,
+        };
+        let start = std::time::Instant::now();
+        while is_running() {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
     #[test]
     fn test_kill() {
         let (_, tapyrusd, mut electrsd) = setup_nodes();
@@ -451,7 +1129,7 @@ mod test {
         assert!(electrsd.client.ping().is_err());
     }
 
-    pub(crate) fn setup_nodes() -> (String, tapyrusd::TapyrusD, ElectrsD) {
+    pub(crate) fn setup_tapyrusd() -> (String, tapyrusd::TapyrusD) {
         let (tapyrusd_exe, electrs_exe) = init();
         println!("tapyrusd: {}", &tapyrusd_exe);
         println!("electrs: {}", &electrs_exe);
@@ -460,6 +1138,11 @@ mod test {
         conf.p2p = P2P::Yes;
         let tapyrusd: tapyrusd::TapyrusD =
             tapyrusd::TapyrusD::with_conf(&tapyrusd_exe, &conf).unwrap();
+        (electrs_exe, tapyrusd)
+    }
+
+    pub(crate) fn setup_nodes() -> (String, tapyrusd::TapyrusD, ElectrsD) {
+        let (electrs_exe, tapyrusd) = setup_tapyrusd();
         let electrs_conf = crate::Conf {
             view_stderr: log_enabled!(Level::Debug),
             ..Default::default()
@@ -468,6 +1151,33 @@ mod test {
         (electrs_exe, tapyrusd, electrsd)
     }
 
+    /// Send `amount` from the tapyrusd wallet to `address`, returns the transaction id
+    pub(crate) fn send_to(
+        tapyrusd: &tapyrusd::TapyrusD,
+        address: &Address,
+        amount: Amount,
+    ) -> MalFixTxid {
+        tapyrusd
+            .client
+            .send_to_address(address, amount, None, None, None, None, None, None)
+            .unwrap()
+    }
+
+    /// Send `amount` from the tapyrusd wallet to a new address of it, returns the address and the
+    /// transaction id
+    pub(crate) fn send_to_new_address(
+        tapyrusd: &tapyrusd::TapyrusD,
+        amount: Amount,
+    ) -> (Address, MalFixTxid) {
+        let address = tapyrusd
+            .client
+            .get_new_address(None)
+            .unwrap()
+            .assume_checked();
+        let txid = send_to(tapyrusd, &address, amount);
+        (address, txid)
+    }
+
     fn init() -> (String, String) {
         let _ = env_logger::try_init();
         let tapyrusd_exe_path = tapyrusd::exe_path().unwrap();
@@ -0,0 +1,295 @@
+//! In-memory capture of the electrs process output
+//!
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use crate::{ElectrsD, Error};
+
+/// Number of log lines attached to the errors returned by this crate
+pub(crate) const ERROR_LOG_LINES: usize = 30;
+
+/// Bounded buffer keeping the last lines written by electrs on stdout and stderr
+#[derive(Debug)]
+pub(crate) struct LogBuffer {
+    inner: Mutex<Inner>,
+    cond: Condvar,
+}
+
+#[derive(Debug)]
+struct Inner {
+    lines: VecDeque<String>,
+    capacity: usize,
+    /// Total number of lines pushed, used to know which lines have been already inspected
+    pushed: u64,
+    open_readers: usize,
+}
+
+impl LogBuffer {
+    pub(crate) fn new(capacity: usize) -> Arc<Self> {
+        Arc::new(LogBuffer {
+            inner: Mutex::new(Inner {
+                lines: VecDeque::with_capacity(capacity.min(1024)),
+                capacity,
+                pushed: 0,
+                open_readers: 0,
+            }),
+            cond: Condvar::new(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // a panic in a reader thread must not prevent reading the logs
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn push(&self, line: String) {
+        let mut inner = self.lock();
+        inner.pushed += 1;
+        if inner.capacity == 0 {
+            return;
+        }
+        if inner.lines.len() == inner.capacity {
+            inner.lines.pop_front();
+        }
+        inner.lines.push_back(line);
+        self.cond.notify_all();
+    }
+
+    fn push_raw(&self, line: &[u8], tee: bool) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        if tee {
+            eprintln!("{}", line);
+        }
+        self.push(line.to_string());
+    }
+
+    fn reader_closed(&self) {
+        self.lock().open_readers -= 1;
+        self.cond.notify_all();
+    }
+
+    /// Spawn a thread copying every line of `reader` in the buffer, optionally printing them
+    pub(crate) fn capture<R: Read + Send + 'static>(self: &Arc<Self>, reader: R, tee: bool) {
+        self.lock().open_readers += 1;
+        let buffer = Arc::clone(self);
+        thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => buffer.push_raw(&line, tee),
+                }
+            }
+            buffer.reader_closed();
+        });
+    }
+
+    /// Spawn a tokio task copying every line of `reader` in the buffer, optionally printing them
+    #[cfg(feature = "async")]
+    pub(crate) fn capture_async<R>(self: &Arc<Self>, reader: R, tee: bool)
+    where
+        R: tokio::io::AsyncRead + Unpin + Send + 'static,
+    {
+        use tokio::io::AsyncBufReadExt;
+
+        self.lock().open_readers += 1;
+        let buffer = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut reader = tokio::io::BufReader::new(reader);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => buffer.push_raw(&line, tee),
+                }
+            }
+            buffer.reader_closed();
+        });
+    }
+
+    /// Wait up to `timeout` for the captured streams to be closed, used after the process exited
+    /// so that the last lines are not lost
+    pub(crate) fn wait_closed(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        let mut inner = self.lock();
+        while inner.open_readers > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            inner = self
+                .cond
+                .wait_timeout(inner, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Wait up to `timeout` for a line matching `regex`, lines already in the buffer are
+    /// considered too. Returns `None` on timeout or if the captured streams are closed.
+    pub(crate) fn wait_for(&self, regex: &Regex, timeout: Duration) -> Option<String> {
+        let deadline = Instant::now() + timeout;
+        let mut inner = self.lock();
+        let mut checked = inner.pushed - inner.lines.len() as u64;
+        loop {
+            let first = inner.pushed - inner.lines.len() as u64;
+            let skip = checked.saturating_sub(first) as usize;
+            if let Some(line) = inner.lines.iter().skip(skip).find(|l| regex.is_match(l)) {
+                return Some(line.clone());
+            }
+            checked = inner.pushed;
+
+            let now = Instant::now();
+            if now >= deadline || inner.open_readers == 0 {
+                return None;
+            }
+            inner = self
+                .cond
+                .wait_timeout(inner, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// `true` once the captured streams are closed, meaning electrs exited
+    pub(crate) fn is_closed(&self) -> bool {
+        self.lock().open_readers == 0
+    }
+
+    pub(crate) fn lines(&self) -> Vec<String> {
+        self.lock().lines.iter().cloned().collect()
+    }
+
+    pub(crate) fn tail(&self, n: usize) -> Vec<String> {
+        let inner = self.lock();
+        let skip = inner.lines.len().saturating_sub(n);
+        inner.lines.iter().skip(skip).cloned().collect()
+    }
+}
+
+impl ElectrsD {
+    /// Return the electrs output lines kept in memory, oldest first.
+    ///
+    /// At most [crate::Conf::logs_capacity] lines are kept.
+    pub fn logs(&self) -> Vec<String> {
+        self.logs.lines()
+    }
+
+    /// Return the last `n` electrs output lines, oldest first
+    pub fn tail_logs(&self, n: usize) -> Vec<String> {
+        self.logs.tail(n)
+    }
+
+    /// Wait up to `timeout` for electrs to output a line matching the `pattern` regex and
+    /// return the line.
+    ///
+    /// Lines still in memory are considered too, so a line emitted before calling this method
+    /// is found as long as it has not been evicted by the [crate::Conf::logs_capacity] limit.
+    /// Returns [Error::Exited] as soon as electrs exits without outputting it.
+    pub fn wait_for_log(&self, pattern: &str, timeout: Duration) -> Result<String, Error> {
+        let regex = Regex::new(pattern)?;
+        let start = Instant::now();
+        self.logs
+            .wait_for(&regex, timeout)
+            .ok_or_else(|| log_not_found(&self.logs, pattern, start.elapsed()))
+    }
+
+    /// Build an [Error::Timeout] with the last electrs output lines
+    pub(crate) fn timeout_error(&self, what: String, elapsed: Duration) -> Error {
+        Error::Timeout {
+            what,
+            elapsed,
+            logs: self.tail_logs(ERROR_LOG_LINES),
+        }
+    }
+}
+
+/// Error returned when no line matching `pattern` is output after waiting `elapsed`:
+/// [Error::Exited] if electrs exited, [Error::Timeout] otherwise
+pub(crate) fn log_not_found(logs: &LogBuffer, pattern: &str, elapsed: Duration) -> Error {
+    let what = format!("log line matching `{}`", pattern);
+    let tail = logs.tail(ERROR_LOG_LINES);
+    if logs.is_closed() {
+        Error::Exited { what, logs: tail }
+    } else {
+        Error::Timeout {
+            what,
+            elapsed,
+            logs: tail,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LogBuffer;
+    use crate::test::setup_nodes;
+    use crate::Error;
+    use regex::Regex;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_log_buffer() {
+        let buffer = LogBuffer::new(2);
+        buffer.capture(&b"first\nsecond\r\nthird"[..], false);
+        buffer.wait_closed(Duration::from_secs(5));
+        assert_eq!(buffer.lines(), vec!["second", "third"]);
+        assert_eq!(buffer.tail(1), vec!["third"]);
+        assert_eq!(buffer.tail(5), vec!["second", "third"]);
+    }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn test_wait_for() {
+        let buffer = LogBuffer::new(10);
+        let (mut writer, reader) = std::os::unix::net::UnixStream::pair().unwrap();
+        buffer.capture(reader, false);
+        let timeout = Duration::from_secs(5);
+
+        std::io::Write::write_all(&mut writer, b"starting\n").unwrap();
+        let regex = Regex::new("^start").unwrap();
+        assert_eq!(buffer.wait_for(&regex, timeout).unwrap(), "starting");
+
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            std::io::Write::write_all(&mut writer, b"finished full compaction\n").unwrap();
+            writer
+        });
+        let regex = Regex::new("full compaction").unwrap();
+        assert_eq!(
+            buffer.wait_for(&regex, timeout).unwrap(),
+            "finished full compaction"
+        );
+
+        let regex = Regex::new("never").unwrap();
+        assert!(buffer
+            .wait_for(&regex, Duration::from_millis(100))
+            .is_none());
+        assert!(!buffer.is_closed());
+        drop(handle.join().unwrap());
+        buffer.wait_closed(timeout);
+        assert!(buffer.is_closed());
+    }
+
+    #[test]
+    fn test_wait_for_log_exited() {
+        let (_, _tapyrusd, mut electrsd) = setup_nodes();
+        electrsd.kill().unwrap();
+        let start = Instant::now();
+        match electrsd.wait_for_log("never", Duration::from_secs(60)) {
+            Err(Error::Exited { what, .. }) => assert!(what.contains("never")),
+            e => panic!("unexpected result {:?}", e),
+        }
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,115 @@
+//! Scraping of the prometheus monitoring endpoint
+//!
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::{http, ElectrsD, Error};
+
+/// Fetch the metrics exposed at `addr` and parse them with [parse]
+pub(crate) fn scrape(addr: &SocketAddr) -> Result<HashMap<String, f64>, Error> {
+    let (status, body) = http::get(addr, "/", Duration::from_secs(10))?;
+    if status != 200 {
+        return Err(Error::InvalidResponse(format!(
+            "monitoring endpoint returned status {}",
+            status
+        )));
+    }
+    parse(&body)
+}
+
+/// Parse the prometheus text exposition format into a map from the sample name, including the
+/// labels as exposed (eg. `electrs_index_height{type="tip"}`), to its value.
+///
+/// Comments and `# HELP` / `# TYPE` lines are skipped, timestamps are ignored.
+pub(crate) fn parse(text: &str) -> Result<HashMap<String, f64>, Error> {
+    let mut metrics = HashMap::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || Error::InvalidResponse(format!("invalid metric line `{}`", line));
+        // label values may contain spaces, the name ends after the closing brace
+        let name_end = match line.find('{') {
+            Some(_) => line.rfind('}').ok_or_else(invalid)? + 1,
+            None => line.find(char::is_whitespace).ok_or_else(invalid)?,
+        };
+        let (name, rest) = line.split_at(name_end);
+        let value = rest
+            .split_whitespace()
+            .next()
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(invalid)?;
+        metrics.insert(name.to_string(), value);
+    }
+    Ok(metrics)
+}
+
+impl ElectrsD {
+    /// Address of the prometheus monitoring endpoint, `None` if
+    /// [crate::Conf::monitoring_enabled] is not set
+    pub fn monitoring_addr(&self) -> Option<SocketAddr> {
+        self.monitoring_addr
+    }
+
+    /// Scrape the prometheus monitoring endpoint, returns every sample keyed by its name and
+    /// labels as exposed, like `electrs_index_height{type="tip"}`.
+    ///
+    /// Useful to assert on indexing counters, RPC latencies and DB sizes. Returns
+    /// [Error::MonitoringDisabled] if [crate::Conf::monitoring_enabled] is not set.
+    pub fn metrics(&self) -> Result<HashMap<String, f64>, Error> {
+        let addr = self.monitoring_addr.ok_or(Error::MonitoringDisabled)?;
+        scrape(&addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+    use crate::test::setup_nodes;
+    use crate::ElectrsD;
+
+    #[test]
+    fn test_parse() {
+        let text = r#"# HELP electrs_index_height Indexed block height
+# TYPE electrs_index_height gauge
+electrs_index_height{type="tip"} 101
+electrs_electrum_rpc_bucket{method="blockchain.headers.subscribe",le="+Inf"} 3 1660000000000
+process_open_fds 42
+
+electrs_db_size{label="with space"} 1.5e3
+"#;
+        let metrics = parse(text).unwrap();
+        assert_eq!(metrics.len(), 4);
+        assert_eq!(metrics[r#"electrs_index_height{type="tip"}"#], 101.0);
+        assert_eq!(
+            metrics[r#"electrs_electrum_rpc_bucket{method="blockchain.headers.subscribe",le="+Inf"}"#],
+            3.0
+        );
+        assert_eq!(metrics["process_open_fds"], 42.0);
+        assert_eq!(metrics[r#"electrs_db_size{label="with space"}"#], 1500.0);
+
+        assert!(parse("no_value").is_err());
+        assert!(parse("name not_a_number").is_err());
+    }
+
+    #[test]
+    fn test_metrics() {
+        let (electrs_exe, tapyrusd, electrsd) = setup_nodes();
+        assert!(electrsd.monitoring_addr().unwrap().ip().is_loopback());
+        let metrics = electrsd.metrics().unwrap();
+        assert!(!metrics.is_empty());
+
+        let conf = crate::Conf {
+            monitoring_enabled: false,
+            ..Default::default()
+        };
+        let electrsd = ElectrsD::with_conf(&electrs_exe, &tapyrusd, &conf).unwrap();
+        assert_eq!(electrsd.monitoring_addr(), None);
+        assert!(matches!(
+            electrsd.metrics(),
+            Err(crate::Error::MonitoringDisabled)
+        ));
+    }
+}
@@ -0,0 +1,517 @@
+//! Fault-injecting TCP proxy, used to test how clients handle slow, broken or unresponsive
+//! servers
+//!
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::{connectable, ElectrsD, Error};
+
+/// In-process TCP proxy forwarding every accepted connection to a target address, with controls
+/// to inject faults on demand.
+///
+/// Faults apply to the connections already open and to the ones accepted afterwards, until
+/// changed or [Proxy::reset]. The proxy stops accepting connections and closes the open ones
+/// when dropped.
+#[derive(Debug)]
+pub struct Proxy {
+    target: SocketAddr,
+    shared: Arc<Shared>,
+    listener: Listener,
+}
+
+#[derive(Debug)]
+struct Shared {
+    faults: Mutex<Faults>,
+    cond: Condvar,
+    connections: Arc<Connections>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Faults {
+    latency: Duration,
+    stalled: bool,
+    blackhole: bool,
+    truncate: Option<usize>,
+}
+
+/// Direction of the traffic forwarded by a connection thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    /// From the client to the target
+    Request,
+    /// From the target to the client
+    Response,
+}
+
+/// Accepts connections on a port chosen by the OS and connects each of them to a target address,
+/// the traffic is forwarded by the threads spawned by the `on_open` callback of
+/// [Listener::bind]. Shared by [Proxy] and the TLS terminator.
+///
+/// Stops accepting connections and closes the open ones when dropped.
+#[derive(Debug)]
+pub(crate) struct Listener {
+    addr: SocketAddr,
+    connections: Arc<Connections>,
+}
+
+/// Registry of the connections open through a [Listener]
+#[derive(Debug)]
+pub(crate) struct Connections {
+    target: SocketAddr,
+    /// Open connections by id, the client and the target side
+    open: Mutex<HashMap<u64, (TcpStream, TcpStream)>>,
+    next_id: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl Connections {
+    pub(crate) fn new(target: SocketAddr) -> Self {
+        Connections {
+            target,
+            open: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    fn registry(&self) -> MutexGuard<'_, HashMap<u64, (TcpStream, TcpStream)>> {
+        self.open.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn is_open(&self, id: u64) -> bool {
+        self.registry().contains_key(&id)
+    }
+
+    /// Shut down both sides of the connection `id`, unblocking its forwarding threads
+    pub(crate) fn close(&self, id: u64) {
+        if let Some((client, target)) = self.registry().remove(&id) {
+            let _ = client.shutdown(Shutdown::Both);
+            let _ = target.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn close_all(&self) {
+        let connections: Vec<_> = self.registry().drain().map(|(_, c)| c).collect();
+        for (client, target) in connections {
+            let _ = client.shutdown(Shutdown::Both);
+            let _ = target.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.registry().len()
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Connect `client` to the target and register the connection, returning its id and both
+    /// sides
+    fn connect(&self, client: TcpStream) -> Result<(u64, TcpStream, TcpStream), Error> {
+        let target = TcpStream::connect(self.target)?;
+        client.set_nodelay(true)?;
+        target.set_nodelay(true)?;
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.registry()
+            .insert(id, (client.try_clone()?, target.try_clone()?));
+        Ok((id, client, target))
+    }
+}
+
+impl Listener {
+    /// Listen on `bind_addr` and for every accepted connection, once connected to the target of
+    /// `connections`, call `on_open` with its id, the client and the target side
+    pub(crate) fn bind<F>(
+        bind_addr: IpAddr,
+        connections: Arc<Connections>,
+        on_open: F,
+    ) -> Result<Self, Error>
+    where
+        F: Fn(u64, TcpStream, TcpStream) -> Result<(), Error> + Send + 'static,
+    {
+        let listener = TcpListener::bind((bind_addr, 0))?;
+        let addr = connectable(listener.local_addr()?);
+
+        let accept_connections = Arc::clone(&connections);
+        thread::spawn(move || {
+            for client in listener.incoming() {
+                if accept_connections.is_closed() {
+                    break;
+                }
+                let client = match client {
+                    Ok(client) => client,
+                    Err(_) => continue,
+                };
+                let opened = accept_connections
+                    .connect(client)
+                    .and_then(|(id, client, target)| {
+                        on_open(id, client, target).map_err(|e| {
+                            accept_connections.close(id);
+                            e
+                        })
+                    });
+                if let Err(e) = opened {
+                    debug!("connection to {} failed: {}", accept_connections.target, e);
+                }
+            }
+        });
+
+        Ok(Listener { addr, connections })
+    }
+
+    /// Address to connect to
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stop accepting connections and close the open ones
+    fn shutdown(&self) {
+        self.connections.closed.store(true, Ordering::SeqCst);
+        self.connections.close_all();
+        // wake up the accepting thread so that it notices the listener is closed
+        let _ = TcpStream::connect_timeout(&self.addr, Duration::from_secs(1));
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Proxy {
+    /// Start a proxy listening on a loopback port chosen by the OS and forwarding to `target`
+    pub fn new(target: SocketAddr) -> Result<Self, Error> {
+        Proxy::bind(target, IpAddr::V4(Ipv4Addr::LOCALHOST))
+    }
+
+    /// Start a proxy listening on `bind_addr` with a port chosen by the OS and forwarding to
+    /// `target`
+    pub fn bind(target: SocketAddr, bind_addr: IpAddr) -> Result<Self, Error> {
+        let connections = Arc::new(Connections::new(target));
+        let shared = Arc::new(Shared {
+            faults: Mutex::new(Faults::default()),
+            cond: Condvar::new(),
+            connections: Arc::clone(&connections),
+        });
+
+        let accept_shared = Arc::clone(&shared);
+        let listener = Listener::bind(bind_addr, connections, move |id, client, target| {
+            accept_shared.open(id, client, target)
+        })?;
+
+        Ok(Proxy {
+            target,
+            shared,
+            listener,
+        })
+    }
+
+    /// Address to connect to the proxy
+    pub fn addr(&self) -> SocketAddr {
+        self.listener.addr()
+    }
+
+    /// Url to connect to the proxy, like `tcp://127.0.0.1:50001`
+    pub fn url(&self) -> String {
+        format!("tcp://{}", self.addr())
+    }
+
+    /// Address the connections are forwarded to
+    pub fn target(&self) -> SocketAddr {
+        self.target
+    }
+
+    /// Delay every forwarded chunk of data, in both directions, by `latency`
+    pub fn set_latency(&self, latency: Duration) {
+        self.shared.update(|faults| faults.latency = latency);
+    }
+
+    /// if `true` data is not forwarded anymore, in both directions, while connections are kept
+    /// open. Pending data is delivered once set back to `false`
+    pub fn set_stalled(&self, stalled: bool) {
+        self.shared.update(|faults| faults.stalled = stalled);
+    }
+
+    /// if `true` data is discarded, in both directions, while connections are kept open and new
+    /// ones are accepted. Unlike [Proxy::set_stalled], discarded data is never delivered
+    pub fn set_blackhole(&self, blackhole: bool) {
+        self.shared.update(|faults| faults.blackhole = blackhole);
+    }
+
+    /// When `Some(n)`, the first chunk of data sent by the target on every connection is cut
+    /// after `n` bytes and the connection is closed
+    pub fn set_truncate(&self, truncate: Option<usize>) {
+        self.shared.update(|faults| faults.truncate = truncate);
+    }
+
+    /// Remove every fault, [Proxy::disconnect_all] effects are not reverted
+    pub fn reset(&self) {
+        self.shared.update(|faults| *faults = Faults::default());
+    }
+
+    /// Close every open connection, new connections are accepted as usual
+    pub fn disconnect_all(&self) {
+        self.shared.connections.close_all();
+        self.shared.notify();
+    }
+
+    /// Number of connections currently open
+    pub fn open_connections(&self) -> usize {
+        self.shared.connections.len()
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        self.listener.shutdown();
+        self.shared.notify();
+    }
+}
+
+impl Shared {
+    fn faults(&self) -> MutexGuard<'_, Faults> {
+        self.faults.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update<F: FnOnce(&mut Faults)>(&self, f: F) {
+        f(&mut self.faults());
+        self.cond.notify_all();
+    }
+
+    /// Wake up the threads waiting in [Shared::wait_not_stalled], the lock is taken so that a
+    /// thread checking the connection state before waiting doesn't miss the notification
+    fn notify(&self) {
+        let _faults = self.faults();
+        self.cond.notify_all();
+    }
+
+    fn close(&self, id: u64) {
+        self.connections.close(id);
+        self.notify();
+    }
+
+    /// Spawn the threads forwarding the traffic of the connection `id`
+    fn open(self: &Arc<Self>, id: u64, client: TcpStream, target: TcpStream) -> Result<(), Error> {
+        let shared = Arc::clone(self);
+        let (request_src, request_dst) = (client.try_clone()?, target.try_clone()?);
+        thread::spawn(move || shared.forward(id, request_src, request_dst, Direction::Request));
+        let shared = Arc::clone(self);
+        thread::spawn(move || shared.forward(id, target, client, Direction::Response));
+        Ok(())
+    }
+
+    fn forward(&self, id: u64, mut src: TcpStream, mut dst: TcpStream, direction: Direction) {
+        let mut buf = [0u8; 16 * 1024];
+        loop {
+            let n = match src.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            // data read while stalled is held until the proxy is resumed
+            let faults = *self.wait_not_stalled(id);
+            if !self.connections.is_open(id) {
+                break;
+            }
+            if faults.blackhole {
+                continue;
+            }
+            if !faults.latency.is_zero() {
+                thread::sleep(faults.latency);
+            }
+            match faults.truncate {
+                Some(len) if direction == Direction::Response => {
+                    let _ = dst.write_all(&buf[..n.min(len)]);
+                    break;
+                }
+                _ => {
+                    if dst.write_all(&buf[..n]).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        self.close(id);
+    }
+
+    /// Block while the proxy is stalled and the connection `id` is open
+    fn wait_not_stalled(&self, id: u64) -> MutexGuard<'_, Faults> {
+        let mut faults = self.faults();
+        while faults.stalled && !self.connections.is_closed() && self.connections.is_open(id) {
+            faults = self.cond.wait(faults).unwrap_or_else(|e| e.into_inner());
+        }
+        faults
+    }
+}
+
+impl ElectrsD {
+    /// Fault-injecting proxy in front of the electrum endpoint, `None` if
+    /// [crate::Conf::proxy_enabled] is not set.
+    ///
+    /// Clients connecting to [Proxy::url] instead of [ElectrsD::electrum_url] can be exposed to
+    /// latency, disconnections, stalls, truncated responses and black-holed traffic.
+    pub fn proxy(&self) -> Option<&Proxy> {
+        self.proxy.as_ref()
+    }
+
+    /// Fault-injecting proxy between electrs and the tapyrusd rpc port, `None` if
+    /// [crate::Conf::daemon_proxy_enabled] is not set.
+    ///
+    /// Useful to simulate node outages and observe electrs recovery without stopping tapyrusd,
+    /// for example with [Proxy::set_blackhole] and [Proxy::disconnect_all].
+    pub fn daemon_rpc_proxy(&self) -> Option<&Proxy> {
+        self.daemon_rpc_proxy.as_ref()
+    }
+
+    /// Fault-injecting proxy between electrs and the tapyrusd p2p port, `None` if
+    /// [crate::Conf::daemon_proxy_enabled] is not set or electrs imports blocks through rpc
+    /// (`legacy`, `electrs_0_5_0` and `electrs_0_5_1` features)
+    pub fn daemon_p2p_proxy(&self) -> Option<&Proxy> {
+        self.daemon_p2p_proxy.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Proxy;
+    use crate::test::setup_tapyrusd;
+    use crate::ElectrsD;
+    use electrum_client::raw_client::RawClient;
+    use electrum_client::ElectrumApi;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::{Duration, Instant};
+
+    /// Start a server echoing back everything it receives
+    fn echo_server() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                std::thread::spawn(move || {
+                    let mut reader = stream.try_clone().unwrap();
+                    let _ = std::io::copy(&mut reader, &mut stream);
+                });
+            }
+        });
+        addr
+    }
+
+    fn echo(stream: &mut TcpStream, msg: &[u8]) -> std::io::Result<Vec<u8>> {
+        stream.write_all(msg)?;
+        let mut buf = vec![0u8; msg.len()];
+        stream.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_proxy_faults() {
+        let proxy = Proxy::new(echo_server()).unwrap();
+        let timeout = Some(Duration::from_millis(500));
+
+        let mut stream = TcpStream::connect(proxy.addr()).unwrap();
+        stream.set_read_timeout(timeout).unwrap();
+        assert_eq!(echo(&mut stream, b"hello").unwrap(), b"hello");
+        assert_eq!(proxy.open_connections(), 1);
+
+        proxy.set_latency(Duration::from_millis(100));
+        let start = Instant::now();
+        assert_eq!(echo(&mut stream, b"slow").unwrap(), b"slow");
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        proxy.reset();
+
+        proxy.set_stalled(true);
+        assert!(echo(&mut stream, b"stalled").is_err());
+        proxy.set_stalled(false);
+        let mut buf = [0u8; 7];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"stalled");
+
+        proxy.set_blackhole(true);
+        assert!(echo(&mut stream, b"lost").is_err());
+        proxy.reset();
+
+        proxy.set_truncate(Some(2));
+        stream.write_all(b"truncated").unwrap();
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"tr");
+        proxy.reset();
+
+        let mut stream = TcpStream::connect(proxy.addr()).unwrap();
+        stream.set_read_timeout(timeout).unwrap();
+        assert_eq!(echo(&mut stream, b"again").unwrap(), b"again");
+        proxy.disconnect_all();
+        let mut buf = vec![];
+        assert_eq!(stream.read_to_end(&mut buf).unwrap_or(0), 0);
+        assert_eq!(proxy.open_connections(), 0);
+    }
+
+    #[test]
+    fn test_electrsd_proxy() {
+        let (electrs_exe, tapyrusd) = setup_tapyrusd();
+        let conf = crate::Conf {
+            proxy_enabled: true,
+            ..Default::default()
+        };
+        let electrsd = ElectrsD::with_conf(&electrs_exe, &tapyrusd, &conf).unwrap();
+        let proxy = electrsd.proxy().unwrap();
+        assert_eq!(proxy.target(), electrsd.electrum_addr());
+
+        let client = RawClient::new(proxy.addr(), None).unwrap();
+        assert_eq!(client.block_headers_subscribe().unwrap().height, 1);
+        proxy.disconnect_all();
+        assert!(client.ping().is_err());
+
+        let client = RawClient::new(proxy.addr(), None).unwrap();
+        client.ping().unwrap();
+    }
+
+    #[test]
+    fn test_daemon_proxy() {
+        let (electrs_exe, tapyrusd) = setup_tapyrusd();
+        let conf = crate::Conf {
+            daemon_proxy_enabled: true,
+            ..Default::default()
+        };
+        let electrsd = ElectrsD::with_conf(&electrs_exe, &tapyrusd, &conf).unwrap();
+        let proxy = electrsd.daemon_rpc_proxy().unwrap();
+        assert_eq!(
+            proxy.target(),
+            std::net::SocketAddr::from(tapyrusd.params.rpc_socket)
+        );
+
+        // node outage, blocks mined meanwhile are indexed once the node is reachable again
+        proxy.set_blackhole(true);
+        proxy.disconnect_all();
+        let address = tapyrusd
+            .client
+            .get_new_address(None)
+            .unwrap()
+            .assume_checked();
+        tapyrusd
+            .client
+            .generate_to_address(10, &address, tapyrusd::get_private_key())
+            .unwrap();
+        electrsd.trigger().unwrap();
+        assert!(electrsd
+            .wait_height_timeout(11, Duration::from_secs(2))
+            .is_err());
+
+        proxy.reset();
+        electrsd.trigger().unwrap();
+        electrsd
+            .wait_height_timeout(11, Duration::from_secs(60))
+            .unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+//! In-process TLS terminator in front of the electrum endpoint, enabled by the `tls` feature
+//!
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use electrum_client::raw_client::{ElectrumSslStream, RawClient};
+use log::debug;
+use rustls::{Certificate, PrivateKey, ServerConfig, ServerConnection};
+
+use crate::proxy::{Connections, Listener};
+use crate::{ElectrsD, Error};
+
+/// Name of the CA certificate file written in the electrs workdir
+const CA_FILE: &str = "electrsd-ca.pem";
+
+/// Accepts TLS connections and forwards the decrypted traffic to the electrum endpoint.
+///
+/// Every connection has a thread per direction blocking on its socket, the rustls state is
+/// shared behind a mutex.
+#[derive(Debug)]
+pub(crate) struct TlsProxy {
+    ca_path: PathBuf,
+    listener: Listener,
+}
+
+impl TlsProxy {
+    /// Generate a self-signed certificate, write it in `dir` and start accepting connections on
+    /// `bind_addr` forwarding them to `target`
+    pub(crate) fn start(target: SocketAddr, bind_addr: IpAddr, dir: &Path) -> Result<Self, Error> {
+        let cert = rcgen::generate_simple_self_signed(vec![
+            "localhost".to_string(),
+            target.ip().to_string(),
+        ])?;
+        let ca_path = dir.join(CA_FILE);
+        fs::write(&ca_path, cert.serialize_pem()?)?;
+
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(cert.serialize_der()?)],
+                PrivateKey(cert.serialize_private_key_der()),
+            )?;
+        let config = Arc::new(config);
+
+        let connections = Arc::new(Connections::new(target));
+        let accept_connections = Arc::clone(&connections);
+        let listener = Listener::bind(bind_addr, connections, move |id, client, target| {
+            open(&accept_connections, &config, id, client, target)
+        })?;
+
+        Ok(TlsProxy { ca_path, listener })
+    }
+
+    /// Address to connect to the TLS endpoint
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.listener.addr()
+    }
+}
+
+/// Spawn the threads decrypting and encrypting the traffic of the connection `id`
+fn open(
+    connections: &Arc<Connections>,
+    config: &Arc<ServerConfig>,
+    id: u64,
+    client: TcpStream,
+    target: TcpStream,
+) -> Result<(), Error> {
+    let tls = Arc::new(Mutex::new(ServerConnection::new(Arc::clone(config))?));
+
+    let request_connections = Arc::clone(connections);
+    let request_tls = Arc::clone(&tls);
+    let (request_src, request_dst) = (client.try_clone()?, target.try_clone()?);
+    thread::spawn(move || {
+        if let Err(e) = decrypt(&request_tls, request_src, request_dst) {
+            debug!("tls connection closed: {}", e);
+        }
+        request_connections.close(id);
+    });
+    let response_connections = Arc::clone(connections);
+    thread::spawn(move || {
+        if let Err(e) = encrypt(&tls, target, client) {
+            debug!("tls connection closed: {}", e);
+        }
+        response_connections.close(id);
+    });
+    Ok(())
+}
+
+fn lock(tls: &Mutex<ServerConnection>) -> MutexGuard<'_, ServerConnection> {
+    tls.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Read TLS records from `client` and forward the decrypted data to `target`, until the client
+/// closes the connection
+fn decrypt(
+    tls: &Mutex<ServerConnection>,
+    mut client: TcpStream,
+    mut target: TcpStream,
+) -> Result<(), Error> {
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        let n = client.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        let mut records = &buf[..n];
+        let mut plaintext = vec![];
+        let mut peer_closed = false;
+        {
+            let mut tls = lock(tls);
+            while !records.is_empty() {
+                tls.read_tls(&mut records)?;
+                let state = match tls.process_new_packets() {
+                    Ok(state) => state,
+                    Err(e) => {
+                        // let the client know why the connection is closed
+                        let _ = write_records(&mut tls, &mut client);
+                        return Err(e.into());
+                    }
+                };
+                let len = plaintext.len();
+                plaintext.resize(len + state.plaintext_bytes_to_read(), 0);
+                tls.reader().read_exact(&mut plaintext[len..])?;
+                peer_closed |= state.peer_has_closed();
+            }
+            // handshake messages, and data buffered by `encrypt` before the handshake completed
+            write_records(&mut tls, &mut client)?;
+        }
+        target.write_all(&plaintext)?;
+        if peer_closed {
+            return Ok(());
+        }
+    }
+}
+
+/// Read data from `target` and forward it encrypted to `client`, until the target closes the
+/// connection
+fn encrypt(
+    tls: &Mutex<ServerConnection>,
+    mut target: TcpStream,
+    mut client: TcpStream,
+) -> Result<(), Error> {
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        let n = target.read(&mut buf)?;
+        let mut tls = lock(tls);
+        if n == 0 {
+            tls.send_close_notify();
+            write_records(&mut tls, &mut client)?;
+            return Ok(());
+        }
+        tls.writer().write_all(&buf[..n])?;
+        write_records(&mut tls, &mut client)?;
+    }
+}
+
+/// Write the pending TLS records to `client`
+fn write_records(tls: &mut ServerConnection, client: &mut TcpStream) -> io::Result<()> {
+    while tls.wants_write() {
+        tls.write_tls(client)?;
+    }
+    Ok(())
+}
+
+impl ElectrsD {
+    /// Url to connect to the electrum protocol over TLS, like `ssl://127.0.0.1:50002`, `None`
+    /// if [crate::Conf::tls_enabled] is not set
+    pub fn electrum_ssl_url(&self) -> Option<String> {
+        self.tls.as_ref().map(|tls| format!("ssl://{}", tls.addr()))
+    }
+
+    /// Address to connect to the electrum protocol over TLS, `None` if
+    /// [crate::Conf::tls_enabled] is not set
+    pub fn electrum_ssl_addr(&self) -> Option<SocketAddr> {
+        self.tls.as_ref().map(|tls| tls.addr())
+    }
+
+    /// Path of the PEM encoded self-signed certificate used by the TLS endpoint, to be trusted
+    /// by clients validating the server certificate. `None` if [crate::Conf::tls_enabled] is
+    /// not set
+    pub fn tls_ca_path(&self) -> Option<&Path> {
+        self.tls.as_ref().map(|tls| tls.ca_path.as_path())
+    }
+}
+
+/// Connect a [RawClient] to the TLS endpoint, the certificate is not validated
+pub(crate) fn connect(addr: SocketAddr) -> Result<RawClient<ElectrumSslStream>, Error> {
+    Ok(RawClient::new_ssl(addr, false, None)?)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::setup_tapyrusd;
+    use crate::ElectrsD;
+    use electrum_client::ElectrumApi;
+
+    #[test]
+    fn test_tls() {
+        let (electrs_exe, tapyrusd) = setup_tapyrusd();
+        let conf = crate::Conf {
+            tls_enabled: true,
+            ..Default::default()
+        };
+        let electrsd = ElectrsD::with_conf(&electrs_exe, &tapyrusd, &conf).unwrap();
+        let url = electrsd.electrum_ssl_url().unwrap();
+        assert!(url.starts_with("ssl://127.0.0.1:"));
+        let pem = std::fs::read_to_string(electrsd.tls_ca_path().unwrap()).unwrap();
+        assert!(pem.starts_with("-----BEGIN CERTIFICATE-----"));
+
+        let tls_client = electrsd.tls_client.as_ref().unwrap();
+        let header = tls_client.block_headers_subscribe().unwrap();
+        assert_eq!(header.height, 1);
+        tls_client.ping().unwrap();
+
+        let electrsd = ElectrsD::new(&electrs_exe, &tapyrusd).unwrap();
+        assert!(electrsd.tls_client.is_none());
+        assert_eq!(electrsd.electrum_ssl_url(), None);
+    }
+}
@@ -9,6 +9,53 @@ const VERSION: &str = "NA";
 
 pub const HAS_FEATURE: bool = cfg!(any(feature = "electrs_0_5_0", feature = "electrs_0_5_1",));
 
-pub fn electrs_name() -> String {
-    format!("esplora-tapyrus-{}-x86_64-unknown-linux-gnu", VERSION)
+/// Target triples of the electrs archives published for every version
+const RELEASES: &[(&str, &[&str])] = &[
+    ("v0.5.0", &["x86_64-unknown-linux-gnu"]),
+    ("v0.5.1", &["x86_64-unknown-linux-gnu"]),
+];
+
+/// Return the target triple of the electrs archives for the given `CARGO_CFG_TARGET_ARCH`,
+/// `CARGO_CFG_TARGET_OS` and `CARGO_CFG_TARGET_ENV` values, `None` for unknown platforms
+pub fn target_triple(arch: &str, os: &str, env: &str) -> Option<String> {
+    match os {
+        "linux" => Some(format!("{}-unknown-linux-{}", arch, env)),
+        "macos" => Some(format!("{}-apple-darwin", arch)),
+        "windows" => Some(format!("{}-pc-windows-{}", arch, env)),
+        _ => None,
+    }
+}
+
+/// Target triples for which the electrs archive of `version` is published
+pub fn supported_targets(version: &str) -> &'static [&'static str] {
+    RELEASES
+        .iter()
+        .find(|(v, _)| *v == version)
+        .map(|(_, targets)| *targets)
+        .unwrap_or(&[])
+}
+
+/// Name of the electrs archive, without extension, for the enabled version and `target`
+pub fn electrs_name(target: &str) -> String {
+    format!("esplora-tapyrus-{}-{}", VERSION, target)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{supported_targets, target_triple};
+
+    #[test]
+    fn test_target_triple() {
+        assert_eq!(
+            target_triple("x86_64", "linux", "gnu").unwrap(),
+            "x86_64-unknown-linux-gnu"
+        );
+        assert_eq!(
+            target_triple("aarch64", "macos", "").unwrap(),
+            "aarch64-apple-darwin"
+        );
+        assert_eq!(target_triple("x86_64", "freebsd", ""), None);
+        assert_eq!(supported_targets("v0.5.1"), &["x86_64-unknown-linux-gnu"]);
+        assert!(supported_targets("v0.1.0").is_empty());
+    }
 }